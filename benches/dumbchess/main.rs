@@ -0,0 +1,134 @@
+// Compare against a saved baseline when changing the board:
+//
+//   cargo bench --bench dumbchess -- --save-baseline before
+//   (make the change)
+//   cargo bench --bench dumbchess -- --baseline before
+//
+// The hashmap_board group replays the same moves on the HashMap-backed board
+// dumbchess used before it moved to a square array, so every run reports moves
+// per second before and after that change. The current board also writes a
+// FEN for every move, which the old one never did.
+mod hashmap_board;
+
+use criterion::{
+  criterion_group, criterion_main, BatchSize, Criterion, Throughput,
+};
+use fantasy_chess::chess_com::GameResponse;
+use fantasy_chess::dumbchess::{Board, Role, Square};
+use fantasy_chess::pgn::VariantPolicy;
+
+// A full chess.com game, including a promotion.
+const GAME_JSON: &str = include_str!("../../src/testdata/game_response.json");
+
+// A move as start and end squares in UCI notation, plus the promotion piece
+struct Move {
+  start: String,
+  end: String,
+  promotion: Option<char>,
+}
+
+fn testdata_moves() -> Vec<Move> {
+  let response: GameResponse = serde_json::from_str(GAME_JSON).unwrap();
+  response
+    .into_record(VariantPolicy::Skip)
+    .unwrap()
+    .moves
+    .into_iter()
+    .map(|m| Move {
+      promotion: m.uci.chars().nth(4),
+      start: m.starting_location,
+      end: m.ending_location,
+    })
+    .collect()
+}
+
+fn replay(moves: &[(Square, Square, Option<Role>)]) -> Board {
+  let mut board = Board::starting();
+  for (start, end, promotion) in moves {
+    board.make_move(start, end, *promotion).unwrap();
+  }
+  board
+}
+
+// The old board only parsed squares from chess.com move lists, where each
+// square is one character, a1 through h8 in this order.
+const MOVE_LIST_SQUARES: &str =
+  "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!?";
+
+fn hashmap_board_square(name: &str) -> hashmap_board::Square {
+  let name = name.as_bytes();
+  let index = (name[0] - b'a') + 8 * (name[1] - b'1');
+  let c = MOVE_LIST_SQUARES.as_bytes()[index as usize] as char;
+  <&hashmap_board::Square>::from(c).clone()
+}
+
+fn hashmap_board_replay(
+  moves: &[(hashmap_board::Square, hashmap_board::Square, Option<i32>)],
+) -> hashmap_board::Board {
+  let mut board = hashmap_board::Board::starting();
+  for (start, end, promotion_value) in moves {
+    board.make_move(start, end, *promotion_value).unwrap();
+  }
+  board
+}
+
+fn bench_replay(c: &mut Criterion) {
+  let moves = testdata_moves();
+  let dumbchess_moves: Vec<(Square, Square, Option<Role>)> = moves
+    .iter()
+    .map(|m| {
+      (
+        Square::parse(m.start.as_bytes()).unwrap(),
+        Square::parse(m.end.as_bytes()).unwrap(),
+        m.promotion.map(|p| match p {
+          'n' => Role::Knight,
+          'b' => Role::Bishop,
+          'r' => Role::Rook,
+          _ => Role::Queen,
+        }),
+      )
+    })
+    .collect();
+  let hashmap_board_moves: Vec<_> = moves
+    .iter()
+    .map(|m| {
+      (
+        hashmap_board_square(&m.start),
+        hashmap_board_square(&m.end),
+        m.promotion.map(|p| match p {
+          'n' | 'b' => 3,
+          'r' => 5,
+          _ => 9,
+        }),
+      )
+    })
+    .collect();
+
+  let mut group = c.benchmark_group("dumbchess");
+  group.throughput(Throughput::Elements(moves.len() as u64));
+  group.bench_function("replay", |b| b.iter(|| replay(&dumbchess_moves)));
+  group.bench_function("replay_and_lifecycles", |b| {
+    b.iter(|| replay(&dumbchess_moves).lifecycles())
+  });
+  group.bench_function("chess_com_into_record", |b| {
+    b.iter_batched(
+      || serde_json::from_str::<GameResponse>(GAME_JSON).unwrap(),
+      |response| response.into_record(VariantPolicy::Skip).unwrap(),
+      BatchSize::SmallInput,
+    )
+  });
+  group.finish();
+
+  let mut group = c.benchmark_group("hashmap_board");
+  group.throughput(Throughput::Elements(moves.len() as u64));
+  group.bench_function("replay", |b| {
+    b.iter(|| hashmap_board_replay(&hashmap_board_moves))
+  });
+  group.bench_function("replay_and_lifecycles", |b| {
+    b.iter(|| hashmap_board_replay(&hashmap_board_moves).lifecycles())
+  });
+  group.finish();
+}
+
+criterion_group!(benches, bench_replay);
+criterion_main!(benches);
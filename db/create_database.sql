@@ -1,6 +1,9 @@
 CREATE DATABASE fantasy_chess;
 
-CREATE USER 'fantasy-chess-rw'@'localhost' IDENTIFIED BY 'faceGuy';
+-- Pick a password when running this, e.g.
+--   sed 's/CHANGE_ME/your-password/' db/create_database.sql | mysql -u root -p
+-- and pass it in the database URL rather than committing it anywhere.
+CREATE USER 'fantasy-chess-rw'@'localhost' IDENTIFIED BY 'CHANGE_ME';
 
 GRANT ALL PRIVILEGES ON fantasy_chess.* TO 'fantasy-chess-rw'@'localhost';
 
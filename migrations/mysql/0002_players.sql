@@ -0,0 +1,44 @@
+-- A real person, who may play under accounts on several sources.
+CREATE TABLE IF NOT EXISTS People (
+  id VARCHAR(36) NOT NULL,
+  name VARCHAR(128) NOT NULL,
+  PRIMARY KEY (id)
+);
+
+-- One account on one source. external_id is what the source identifies the
+-- account by (the username for PGN sources, a numeric ID for chess.com).
+CREATE TABLE IF NOT EXISTS Players (
+  source VARCHAR(64) NOT NULL,
+  external_id VARCHAR(64) NOT NULL,
+  person_id VARCHAR(36),
+  PRIMARY KEY (source, external_id)
+);
+
+-- Every username an account has played under, with the end times of the
+-- first and last games seen under it.
+CREATE TABLE IF NOT EXISTS PlayerUsernames (
+  source VARCHAR(64) NOT NULL,
+  external_id VARCHAR(64) NOT NULL,
+  username VARCHAR(64) NOT NULL,
+  first_seen BIGINT NOT NULL,
+  last_seen BIGINT NOT NULL,
+  PRIMARY KEY (source, external_id, username)
+);
+
+INSERT INTO Players (source, external_id)
+  SELECT source, white_player_id FROM Games
+  UNION
+  SELECT source, black_player_id FROM Games;
+
+INSERT INTO PlayerUsernames
+    (source, external_id, username, first_seen, last_seen)
+  SELECT source, external_id, username, MIN(end_time), MAX(end_time)
+  FROM (
+    SELECT source, white_player_id AS external_id,
+      white_player_name AS username, end_time
+    FROM Games
+    UNION ALL
+    SELECT source, black_player_id, black_player_name, end_time
+    FROM Games
+  ) seen
+  GROUP BY source, external_id, username;
@@ -0,0 +1,5 @@
+ALTER TABLE Moves ADD COLUMN san VARCHAR(16) NOT NULL DEFAULT '';
+ALTER TABLE Moves ADD COLUMN uci VARCHAR(8) NOT NULL DEFAULT '';
+ALTER TABLE Moves ADD COLUMN fen VARCHAR(100);
+ALTER TABLE Moves ADD COLUMN clock_ms BIGINT;
+ALTER TABLE Moves ADD COLUMN elapsed_ms BIGINT;
@@ -0,0 +1,14 @@
+-- Each account's rating going into each game, by time class. Filled in on
+-- ingest; games stored earlier are added by `ratings --rebuild`.
+CREATE TABLE IF NOT EXISTS PlayerRatings (
+  source VARCHAR(64) NOT NULL,
+  player_id VARCHAR(64) NOT NULL,
+  time_class VARCHAR(16) NOT NULL,
+  game_id VARCHAR(36) NOT NULL,
+  rated_at BIGINT NOT NULL,
+  rating INTEGER NOT NULL,
+  PRIMARY KEY (source, player_id, time_class, game_id)
+);
+
+CREATE INDEX player_ratings_by_time
+  ON PlayerRatings (source, player_id, time_class, rated_at);
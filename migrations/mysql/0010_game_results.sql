@@ -0,0 +1,6 @@
+ALTER TABLE Games ADD COLUMN result VARCHAR(7) NOT NULL DEFAULT '*';
+ALTER TABLE Games ADD COLUMN termination VARCHAR(32) NOT NULL DEFAULT '';
+
+-- Points each piece that played a game scores for its holder when its side
+-- wins.
+ALTER TABLE Leagues ADD COLUMN win_points INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+-- Order in which transactions were recorded within a league. Transactions
+-- sharing an effective_time (e.g. the two halves of a trade) replay in seq
+-- order.
+ALTER TABLE RosterTransactions ADD COLUMN seq BIGINT NOT NULL DEFAULT 0;
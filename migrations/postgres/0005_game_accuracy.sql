@@ -0,0 +1,2 @@
+ALTER TABLE Games ADD COLUMN white_accuracy DOUBLE PRECISION;
+ALTER TABLE Games ADD COLUMN black_accuracy DOUBLE PRECISION;
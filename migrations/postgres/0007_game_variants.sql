@@ -0,0 +1 @@
+ALTER TABLE Games ADD COLUMN variant VARCHAR(32) NOT NULL DEFAULT 'standard';
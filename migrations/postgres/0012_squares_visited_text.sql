@@ -0,0 +1,3 @@
+-- A piece that wanders for a long game visits more squares than fit in 1024
+-- characters.
+ALTER TABLE PieceLifecycles ALTER COLUMN squares_visited TYPE TEXT;
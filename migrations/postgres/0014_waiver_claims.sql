@@ -0,0 +1,16 @@
+-- Waiver claims waiting for the league's next waiver run. Processing deletes
+-- them; the claims that win are recorded as waiver_claim transactions.
+CREATE TABLE IF NOT EXISTS WaiverClaims (
+  id VARCHAR(36) NOT NULL,
+  league_id VARCHAR(36) NOT NULL,
+  team_id VARCHAR(36) NOT NULL,
+  source VARCHAR(64) NOT NULL,
+  player_id VARCHAR(64) NOT NULL,
+  piece VARCHAR(16) NOT NULL,
+  drop_source VARCHAR(64),
+  drop_player_id VARCHAR(64),
+  drop_piece VARCHAR(16),
+  bid INTEGER NOT NULL,
+  submitted_time BIGINT NOT NULL,
+  PRIMARY KEY (id)
+);
@@ -0,0 +1,86 @@
+CREATE TABLE IF NOT EXISTS Games (
+  id VARCHAR(36) NOT NULL,
+  source VARCHAR(64) NOT NULL,
+  source_id VARCHAR(64) NOT NULL,
+  end_time BIGINT NOT NULL,
+  time_control VARCHAR(32) NOT NULL,
+  white_player_id VARCHAR(64) NOT NULL,
+  white_player_name VARCHAR(64) NOT NULL,
+  white_player_rating INTEGER NOT NULL,
+  black_player_id VARCHAR(64) NOT NULL,
+  black_player_name VARCHAR(64) NOT NULL,
+  black_player_rating INTEGER NOT NULL,
+  PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS Moves (
+  game_id VARCHAR(36) NOT NULL,
+  move_num INTEGER NOT NULL,
+  color VARCHAR(8) NOT NULL,
+  moved_piece VARCHAR(16) NOT NULL,
+  starting_location CHAR(2) NOT NULL,
+  ending_location CHAR(2) NOT NULL,
+  captured_piece VARCHAR(16) NOT NULL,
+  capture_score INTEGER NOT NULL,
+  promotion_value INTEGER NOT NULL,
+  checkmate BOOLEAN NOT NULL,
+  PRIMARY KEY (game_id, move_num, color)
+);
+
+CREATE TABLE IF NOT EXISTS PieceLifecycles (
+  game_id VARCHAR(36) NOT NULL,
+  color VARCHAR(8) NOT NULL,
+  piece VARCHAR(16) NOT NULL,
+  spawn_square CHAR(2) NOT NULL,
+  squares_visited VARCHAR(1024) NOT NULL,
+  move_count INTEGER NOT NULL,
+  captured_ply INTEGER,
+  captured_by VARCHAR(16) NOT NULL,
+  PRIMARY KEY (game_id, color, piece)
+);
+
+CREATE TABLE IF NOT EXISTS Leagues (
+  id VARCHAR(36) NOT NULL,
+  name VARCHAR(128) NOT NULL,
+  waiver_mode VARCHAR(16) NOT NULL,
+  PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS Teams (
+  id VARCHAR(36) NOT NULL,
+  league_id VARCHAR(36) NOT NULL,
+  name VARCHAR(128) NOT NULL,
+  waiver_priority INTEGER NOT NULL,
+  faab_budget INTEGER NOT NULL,
+  PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS RosterTransactions (
+  id VARCHAR(36) NOT NULL,
+  league_id VARCHAR(36) NOT NULL,
+  team_id VARCHAR(36) NOT NULL,
+  source VARCHAR(64) NOT NULL,
+  player_id VARCHAR(64) NOT NULL,
+  piece VARCHAR(16) NOT NULL,
+  kind VARCHAR(16) NOT NULL,
+  effective_time BIGINT NOT NULL,
+  PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS Trades (
+  id VARCHAR(36) NOT NULL,
+  league_id VARCHAR(36) NOT NULL,
+  proposer_team_id VARCHAR(36) NOT NULL,
+  recipient_team_id VARCHAR(36) NOT NULL,
+  status VARCHAR(16) NOT NULL,
+  proposed_time BIGINT NOT NULL,
+  PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS TradePieces (
+  trade_id VARCHAR(36) NOT NULL,
+  from_team_id VARCHAR(36) NOT NULL,
+  source VARCHAR(64) NOT NULL,
+  player_id VARCHAR(64) NOT NULL,
+  piece VARCHAR(16) NOT NULL
+);
@@ -0,0 +1,4 @@
+ALTER TABLE Moves ADD COLUMN eval_cp INTEGER;
+ALTER TABLE Moves ADD COLUMN eval_mate INTEGER;
+ALTER TABLE Moves ADD COLUMN cp_loss INTEGER;
+ALTER TABLE Moves ADD COLUMN classification VARCHAR(16);
@@ -0,0 +1,2 @@
+ALTER TABLE Games ADD COLUMN white_accuracy REAL;
+ALTER TABLE Games ADD COLUMN black_accuracy REAL;
@@ -0,0 +1,2 @@
+ALTER TABLE Games ADD COLUMN eco VARCHAR(3) NOT NULL DEFAULT '';
+ALTER TABLE Games ADD COLUMN opening VARCHAR(128) NOT NULL DEFAULT '';
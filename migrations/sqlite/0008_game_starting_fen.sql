@@ -0,0 +1 @@
+ALTER TABLE Games ADD COLUMN starting_fen VARCHAR(100);
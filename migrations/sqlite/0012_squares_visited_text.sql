@@ -0,0 +1,17 @@
+-- A piece that wanders for a long game visits more squares than fit in 1024
+-- characters. SQLite doesn't enforce VARCHAR lengths, but rebuild the table
+-- so the declared type matches the other dialects.
+CREATE TABLE PieceLifecycles_new (
+  game_id VARCHAR(36) NOT NULL,
+  color VARCHAR(8) NOT NULL,
+  piece VARCHAR(16) NOT NULL,
+  spawn_square CHAR(2) NOT NULL,
+  squares_visited TEXT NOT NULL,
+  move_count INTEGER NOT NULL,
+  captured_ply INTEGER,
+  captured_by VARCHAR(16) NOT NULL,
+  PRIMARY KEY (game_id, color, piece)
+);
+INSERT INTO PieceLifecycles_new SELECT * FROM PieceLifecycles;
+DROP TABLE PieceLifecycles;
+ALTER TABLE PieceLifecycles_new RENAME TO PieceLifecycles;
@@ -0,0 +1,2 @@
+-- How a league's pieces score their captures: "flat" or "rating_weighted".
+ALTER TABLE Leagues ADD COLUMN scoring VARCHAR(16) NOT NULL DEFAULT 'flat';
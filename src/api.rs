@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Extension, Path, Query},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  routing::get,
+  Json, Router,
+};
+use sqlx::Row;
+use thiserror::Error as ThisError;
+
+use crate::db::{self, Dialect};
+use crate::{league, stats};
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+type Db = Arc<sqlx::Pool<sqlx::Any>>;
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+  #[error("not found")]
+  NotFound,
+  #[error("database error: {source}")]
+  Database {
+    #[from]
+    source: sqlx::Error,
+  },
+  #[error("league error: {source}")]
+  League {
+    #[from]
+    source: league::Error,
+  },
+}
+
+impl IntoResponse for Error {
+  fn into_response(self) -> Response {
+    let status = match &self {
+      Error::NotFound => StatusCode::NOT_FOUND,
+      Error::Database { source: sqlx::Error::RowNotFound } => {
+        StatusCode::NOT_FOUND
+      }
+      Error::League {
+        source: league::Error::Database { source: sqlx::Error::RowNotFound },
+      } => StatusCode::NOT_FOUND,
+      _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let body = serde_json::json!({ "error": self.to_string() });
+    (status, Json(body)).into_response()
+  }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, serde::Serialize)]
+pub struct Page<T> {
+  pub items: Vec<T>,
+  pub limit: i64,
+  pub offset: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct PieceStats {
+  #[serde(flatten)]
+  pub totals: stats::PieceStats,
+  pub average_survival_ply: f64,
+  pub promotion_rate: f64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Roster {
+  pub team: league::Team,
+  pub pieces: Vec<league::PieceId>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Standing {
+  pub team: league::Team,
+  pub score: i64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PageParams {
+  pub limit: Option<i64>,
+  pub offset: Option<i64>,
+}
+
+impl PageParams {
+  fn limit(&self) -> i64 {
+    self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(0, MAX_PAGE_SIZE)
+  }
+
+  fn offset(&self) -> i64 {
+    self.offset.unwrap_or(0).max(0)
+  }
+
+  fn page<T>(&self, items: Vec<T>) -> Page<T> {
+    Page { items, limit: self.limit(), offset: self.offset() }
+  }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TimeParams {
+  // Unix timestamp to evaluate rosters at; defaults to now.
+  pub time: Option<i64>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PeriodParams {
+  // Unix timestamps bounding the games scored, [start, end). Both default to
+  // unbounded.
+  pub start: Option<i64>,
+  pub end: Option<i64>,
+}
+
+// Builds the read-only API. Every route is a GET returning JSON.
+pub fn router(db: Db) -> Router {
+  Router::new()
+    .route("/games", get(list_games))
+    .route("/games/:id", get(get_game))
+    .route("/games/:id/moves", get(list_moves))
+    .route("/games/:id/pieces", get(list_lifecycles))
+    .route("/players", get(list_players))
+    .route("/players/:id/pieces", get(player_pieces))
+    .route("/players/:id/openings", get(player_openings))
+    .route("/players/:id/games_played", get(player_games_played))
+    .route("/leagues", get(list_leagues))
+    .route("/leagues/:id", get(get_league))
+    .route("/leagues/:id/rosters", get(league_rosters))
+    .route("/leagues/:id/standings", get(league_standings))
+    .route("/leagues/:id/matchups", get(league_matchups))
+    .layer(Extension(db))
+}
+
+async fn list_games(
+  Extension(db): Extension<Db>,
+  Query(filter): Query<db::GameFilter>,
+) -> Result<Json<Page<db::Game>>> {
+  let page = PageParams { limit: filter.limit, offset: filter.offset };
+  let filter = db::GameFilter {
+    limit: Some(page.limit()),
+    offset: Some(page.offset()),
+    ..filter
+  };
+  Ok(Json(page.page(db::games(&db, &filter).await?)))
+}
+
+async fn get_game(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+) -> Result<Json<db::Game>> {
+  Ok(Json(db::game(&db, &id).await?.ok_or(Error::NotFound)?))
+}
+
+async fn list_moves(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+) -> Result<Json<Vec<db::Move>>> {
+  Ok(Json(db::moves_for_game(&db, &id).await?))
+}
+
+async fn list_lifecycles(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+) -> Result<Json<Vec<db::PieceLifecycle>>> {
+  Ok(Json(db::lifecycles_for_game(&db, &id).await?))
+}
+
+async fn list_players(
+  Extension(db): Extension<Db>,
+  Query(page): Query<PageParams>,
+) -> Result<Json<Page<db::Player>>> {
+  let players = db::players(&db, page.limit(), page.offset()).await?;
+  Ok(Json(page.page(players)))
+}
+
+async fn player_pieces(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+  Query(filter): Query<stats::Filter>,
+) -> Result<Json<Vec<PieceStats>>> {
+  let pieces = stats::piece_stats(&db, &id, &filter).await?;
+  Ok(Json(
+    pieces
+      .into_iter()
+      .map(|p| PieceStats {
+        average_survival_ply: p.average_survival_ply(),
+        promotion_rate: p.promotion_rate(),
+        totals: p,
+      })
+      .collect(),
+  ))
+}
+
+async fn player_games_played(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+  Query(filter): Query<stats::Filter>,
+) -> Result<Json<stats::GamesPlayed>> {
+  Ok(Json(stats::games_played(&db, &id, &filter).await?))
+}
+
+async fn player_openings(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+  Query(filter): Query<stats::Filter>,
+) -> Result<Json<Vec<stats::OpeningStats>>> {
+  Ok(Json(stats::opening_stats(&db, &id, &filter).await?))
+}
+
+async fn list_leagues(
+  Extension(db): Extension<Db>,
+  Query(page): Query<PageParams>,
+) -> Result<Json<Page<league::League>>> {
+  let rows = sqlx::query(
+    Dialect::of(&db)
+      .sql("SELECT id FROM Leagues ORDER BY name LIMIT ? OFFSET ?"),
+  )
+  .bind(page.limit())
+  .bind(page.offset())
+  .fetch_all(&*db)
+  .await?;
+  let mut leagues = Vec::new();
+  for row in rows {
+    let id: String = row.try_get("id")?;
+    leagues.push(league::League::load(&db, &id).await?);
+  }
+  Ok(Json(page.page(leagues)))
+}
+
+async fn get_league(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+) -> Result<Json<league::League>> {
+  Ok(Json(league::League::load(&db, &id).await?))
+}
+
+async fn league_rosters(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+  Query(params): Query<TimeParams>,
+) -> Result<Json<Vec<Roster>>> {
+  let league = league::League::load(&db, &id).await?;
+  let time = params.time.unwrap_or_else(|| chrono::Utc::now().timestamp());
+  Ok(Json(
+    league
+      .teams
+      .iter()
+      .map(|team| Roster {
+        team: team.clone(),
+        pieces: league.roster(&team.id, time),
+      })
+      .collect(),
+  ))
+}
+
+async fn league_standings(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+) -> Result<Json<Vec<Standing>>> {
+  let league = league::League::load(&db, &id).await?;
+  Ok(Json(
+    league::standings(&db, &league)
+      .await?
+      .into_iter()
+      .map(|(team, score)| Standing { team, score })
+      .collect(),
+  ))
+}
+
+async fn league_matchups(
+  Extension(db): Extension<Db>,
+  Path(id): Path<String>,
+  Query(params): Query<PeriodParams>,
+) -> Result<Json<Vec<league::Matchup>>> {
+  let league = league::League::load(&db, &id).await?;
+  let start = params.start.unwrap_or(i64::MIN);
+  Ok(Json(league::matchups(&db, &league, start, params.end).await?))
+}
@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 
 use crate::db;
-use crate::dumbchess::{Board, Square};
+use crate::dumbchess::{Board, Ending, Role, Square};
+use crate::openings;
+use crate::pgn::VariantPolicy;
 
 // =============================================================================
 // API Types
@@ -20,6 +22,29 @@ pub struct Game {
   pub end_time: i64,
   #[serde(rename = "moveList")]
   pub move_list: String,
+  #[serde(rename = "isCheckmate", default)]
+  pub is_checkmate: bool,
+  #[serde(rename = "isStalemate", default)]
+  pub is_stalemate: bool,
+  // "white" or "black"; absent for draws
+  #[serde(rename = "colorOfWinner", default)]
+  pub color_of_winner: Option<String>,
+  // e.g. "bob won by resignation" or "Game drawn by repetition"
+  #[serde(rename = "resultMessage", default)]
+  pub result_message: String,
+  // Both of these are in tenths of a second. Daily games don't have them.
+  #[serde(rename = "baseTime1", default)]
+  pub base_time: i64,
+  #[serde(rename = "timeIncrement1", default)]
+  pub time_increment: i64,
+  // Comma-separated clock remaining after each move, in tenths of a second.
+  #[serde(rename = "moveTimestamps", default)]
+  pub move_timestamps: String,
+  // e.g. "chess" or "chess960"
+  #[serde(rename = "type", default)]
+  pub game_type: String,
+  #[serde(rename = "isChess960", default)]
+  pub is_chess960: bool,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -36,55 +61,156 @@ pub struct Player {
   rating: i32,
 }
 
-impl db::Recordable for GameResponse {
-  fn game(&self) -> db::Result<db::Game> {
+impl GameResponse {
+  // Translates the game, replaying its moves to work out what happened to
+  // each piece. Games that can't be replayed, like Chess960, are skipped or
+  // stored without moves as the policy says.
+  pub fn into_record(
+    self,
+    variants: VariantPolicy,
+  ) -> Result<db::GameRecord, db::Rejection> {
     let (white_player, black_player) = match self.players.top.color.as_str() {
       "white" => (&self.players.top, &self.players.bottom),
       "black" => (&self.players.bottom, &self.players.top),
-      _ => return Err(db::Error::GameTranslation),
+      color => {
+        return Err(db::Error::UnrecognizedColor(color.to_owned()).into())
+      }
+    };
+    let result = match self.game.color_of_winner.as_deref() {
+      Some("white") => db::WHITE_WON,
+      Some("black") => db::BLACK_WON,
+      Some(color) => {
+        return Err(db::Error::UnrecognizedColor(color.to_owned()).into())
+      }
+      None => db::DRAWN,
     };
-    Ok(db::Game {
+    let description = if self.game.is_stalemate {
+      "stalemate"
+    } else {
+      &self.game.result_message
+    };
+    let mut game = db::Game {
       id: uuid::Uuid::new_v4().to_string(),
       source: "chess.com".to_owned(),
       source_id: self.game.id.to_string(),
       end_time: self.game.end_time,
+      time_control: self.time_control(),
       white_player_id: white_player.id.to_string(),
       white_player_name: white_player.username.clone(),
       white_player_rating: white_player.rating,
       black_player_id: black_player.id.to_string(),
       black_player_name: black_player.username.clone(),
       black_player_rating: black_player.rating,
-    })
+      white_accuracy: None,
+      black_accuracy: None,
+      eco: String::new(),
+      opening: String::new(),
+      variant: if self.game.is_chess960 || self.game.game_type == "chess960" {
+        db::CHESS960_VARIANT
+      } else {
+        db::STANDARD_VARIANT
+      }
+      .to_owned(),
+      starting_fen: None,
+      result: result.to_owned(),
+      termination: db::termination(
+        description,
+        result,
+        Some(Ending::Checkmate).filter(|_| self.game.is_checkmate),
+      ),
+    };
+    if !game.is_replayable() {
+      return match variants {
+        VariantPolicy::Skip => Err(db::Rejection::Nonstandard(game.variant)),
+        VariantPolicy::Metadata => {
+          Ok(db::GameRecord { game, moves: Vec::new(), lifecycles: Vec::new() })
+        }
+      };
+    }
+    let (board, moves) = self.replay()?;
+    if let Some(ending) = board.ending() {
+      game.termination = db::termination(description, result, Some(ending));
+    }
+    openings::classify_game(&mut game, &moves);
+    Ok(db::GameRecord { game, moves, lifecycles: board.lifecycles() })
   }
 
-  fn moves(&self) -> db::Result<Vec<db::Move>> {
+  // Plays the whole move list out on a board, returning the final board along
+  // with each move made.
+  fn replay(&self) -> db::Result<(Board, Vec<db::Move>)> {
     let mut board = Board::starting();
     let mut move_list = self.game.move_list.chars().fuse();
+    let mut clocks = self.clocks().into_iter();
+    let mut last_clock = [self.game.base_time; 2];
     let mut parsed_moves = Vec::new();
 
     while let Some(start) = move_list.next() {
       if let Some(end) = move_list.next() {
         let mut promotion = None;
-        let start_square = start.into();
+        let start_square = Square::from_move_list_char(start)
+          .ok_or(db::Error::UnrecognizedMoveChar(start))?;
         // Promotion is handled here by looking at the end move; if it is a
         // promotion move, it will have a special character that doesn't correspond
         // to any square on the board. From this char, we can deduce:
         // * the promotion square, which is calculated based on the promotion
         //   direction and the starting square
         // * the piece type that this piece is promoted to
-        let end_square = if PROMOTION_DIR.contains_key(&end) {
-          promotion = Some(*PROMOTION_VALUE.get(&end).unwrap());
-          PROMOTION_DIR.get(&end).unwrap().get(start_square).unwrap()
-        } else {
-          end.into()
+        let end_square = match PROMOTION_DIR.get(&end) {
+          Some(dir) => {
+            promotion = PROMOTION_PIECE.get(&end).copied();
+            *dir
+              .get(&start_square)
+              .ok_or(db::Error::UnrecognizedMoveChar(end))?
+          }
+          None => Square::from_move_list_char(end)
+            .ok_or(db::Error::UnrecognizedMoveChar(end))?,
         };
-        let m = board.make_move(start_square, end_square, promotion)?;
+        let mut m = board.make_move(&start_square, &end_square, promotion)?;
+
+        if let Some(clock) = clocks.next() {
+          let side = parsed_moves.len() % 2;
+          m.clock_ms = Some(clock * 100);
+          m.elapsed_ms =
+            Some((last_clock[side] + self.game.time_increment - clock) * 100);
+          last_clock[side] = clock;
+        }
         parsed_moves.push(m);
       } else {
-        return Err(db::Error::GameTranslation);
+        return Err(db::Error::TruncatedMoveList);
+      }
+    }
+    if self.game.is_checkmate {
+      if let Some(last_move) = parsed_moves.last_mut() {
+        last_move.checkmate = true;
       }
     }
-    Ok(parsed_moves)
+    Ok((board, parsed_moves))
+  }
+
+  // Clock readings after each move, in tenths of a second. Older games don't
+  // have any.
+  fn clocks(&self) -> Vec<i64> {
+    self
+      .game
+      .move_timestamps
+      .split(',')
+      .filter_map(|t| t.trim().parse().ok())
+      .collect()
+  }
+
+  // Formats the time control the same way PGN TimeControl headers do: base
+  // time in seconds, followed by the increment if there is one. Games without
+  // a clock get "-", as in PGN.
+  fn time_control(&self) -> String {
+    let base = self.game.base_time / 10;
+    let increment = self.game.time_increment / 10;
+    if base == 0 && increment == 0 {
+      "-".to_owned()
+    } else if increment == 0 {
+      base.to_string()
+    } else {
+      format!("{}+{}", base, increment)
+    }
   }
 }
 
@@ -153,18 +279,18 @@ lazy_static! {
     ']' => &*PROMOTE_RIGHT,
     '$' => &*PROMOTE_RIGHT,
   };
-  static ref PROMOTION_VALUE: HashMap<char, i32> = maplit::hashmap! {
-    '~' => 9,
-    '^' => 3,
-    '_' => 5,
-    '#' => 3,
-    '(' => 3,
-    '{' => 9,
-    '[' => 5,
-    '@' => 3,
-    '}' => 9,
-    ')' => 3,
-    ']' => 5,
-    '$' => 3,
+  static ref PROMOTION_PIECE: HashMap<char, Role> = maplit::hashmap! {
+    '~' => Role::Queen,
+    '^' => Role::Knight,
+    '_' => Role::Rook,
+    '#' => Role::Bishop,
+    '{' => Role::Queen,
+    '(' => Role::Knight,
+    '[' => Role::Rook,
+    '@' => Role::Bishop,
+    '}' => Role::Queen,
+    ')' => Role::Knight,
+    ']' => Role::Rook,
+    '$' => Role::Bishop,
   };
 }
@@ -1,20 +1,248 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
 use crate::dumbchess;
+use crate::eval;
+use itertools::Itertools;
+use sqlx::{any::AnyRow, Row};
 use thiserror::Error as ThisError;
 
+// Why a game from one of the sources couldn't be translated into a record.
 #[derive(ThisError, Debug)]
 pub enum Error {
-  #[error("failed to translate game")]
-  GameTranslation,
-  #[error("failed to translate move")]
+  #[error("unrecognized player color: {0}")]
+  UnrecognizedColor(String),
+  #[error("move list ends partway through a move")]
+  TruncatedMoveList,
+  #[error("unrecognized character in move list: {0:?}")]
+  UnrecognizedMoveChar(char),
+  #[error("invalid {name} header: {value:?}")]
+  InvalidHeader { name: String, value: String },
+  #[error("invalid move {ply} ({san}): {source}")]
+  InvalidMove { ply: u32, san: String, source: dumbchess::Error },
+  #[error("failed to translate move: {source}")]
   MoveTranslation {
     #[from]
     source: dumbchess::Error,
   },
+  #[error("failed to read game: {source}")]
+  Io {
+    #[from]
+    source: std::io::Error,
+  },
+  #[error("failed to write game: {source}")]
+  Insert {
+    #[from]
+    source: sqlx::Error,
+  },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone)]
+impl Error {
+  // A short, stable name for the kind of error, for counting failures.
+  pub fn reason(&self) -> &'static str {
+    match self {
+      Error::UnrecognizedColor(_) => "unrecognized_color",
+      Error::TruncatedMoveList => "truncated_move_list",
+      Error::UnrecognizedMoveChar(_) => "unrecognized_move_char",
+      Error::InvalidHeader { .. } => "invalid_header",
+      Error::InvalidMove { .. } => "invalid_move",
+      Error::MoveTranslation { .. } => "invalid_move",
+      Error::Io { .. } => "read_error",
+      Error::Insert { .. } => "insert_error",
+    }
+  }
+}
+
+// Why a game read from a source wasn't turned into a record.
+#[derive(Debug)]
+pub enum Rejection {
+  // Deliberately passed over, e.g. by an ingest filter
+  Skipped(String),
+  // Not standard chess, e.g. a variant; names what kind of game it was
+  Nonstandard(String),
+  // Couldn't be translated
+  Failed(Error),
+}
+
+impl Rejection {
+  pub const SKIPPED: &'static str = "skipped";
+  pub const NONSTANDARD: &'static str = "nonstandard";
+  pub const FAILED: &'static str = "failed";
+
+  pub fn category(&self) -> &'static str {
+    match self {
+      Rejection::Skipped(_) => Rejection::SKIPPED,
+      Rejection::Nonstandard(_) => Rejection::NONSTANDARD,
+      Rejection::Failed(_) => Rejection::FAILED,
+    }
+  }
+
+  pub fn reason(&self) -> String {
+    match self {
+      Rejection::Skipped(reason) => reason.clone(),
+      Rejection::Nonstandard(kind) => kind.clone(),
+      Rejection::Failed(e) => e.reason().to_owned(),
+    }
+  }
+}
+
+impl std::fmt::Display for Rejection {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Rejection::Skipped(reason) => write!(f, "skipped: {}", reason),
+      Rejection::Nonstandard(kind) => write!(f, "nonstandard game: {}", kind),
+      Rejection::Failed(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl From<Error> for Rejection {
+  fn from(e: Error) -> Rejection {
+    Rejection::Failed(e)
+  }
+}
+
+pub type Query =
+  sqlx::query::Query<'static, sqlx::Any, sqlx::any::AnyArguments<'static>>;
+
+// The SQL flavor spoken by the database behind a pool. Queries in this crate
+// are written with `?` placeholders and translated per dialect.
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
+pub enum Dialect {
+  Sqlite,
+  MySql,
+  Postgres,
+}
+
+// What an insert should do when it collides with an existing row on the given
+// key columns.
+pub enum OnConflict<'a> {
+  Fail,
+  Ignore(&'a [&'a str]),
+  Update(&'a [&'a str]),
+}
+
+lazy_static! {
+  static ref STATEMENTS: Mutex<HashSet<&'static str>> =
+    Mutex::new(HashSet::new());
+}
+
+impl Dialect {
+  pub fn of(db: &sqlx::Pool<sqlx::Any>) -> Dialect {
+    match db.any_kind() {
+      sqlx::any::AnyKind::Sqlite => Dialect::Sqlite,
+      sqlx::any::AnyKind::MySql => Dialect::MySql,
+      sqlx::any::AnyKind::Postgres => Dialect::Postgres,
+    }
+  }
+
+  // Rewrites `?` placeholders into the dialect's own syntax.
+  pub fn placeholders(self, sql: &str) -> String {
+    match self {
+      Dialect::Sqlite | Dialect::MySql => sql.to_owned(),
+      Dialect::Postgres => {
+        let mut rewritten = String::with_capacity(sql.len());
+        let mut num_params = 0;
+        let mut in_literal = false;
+        for c in sql.chars() {
+          match c {
+            '?' if !in_literal => {
+              num_params += 1;
+              rewritten.push_str(&format!("${}", num_params));
+            }
+            '\'' => {
+              in_literal = !in_literal;
+              rewritten.push(c);
+            }
+            c => rewritten.push(c),
+          }
+        }
+        rewritten
+      }
+    }
+  }
+
+  // Like placeholders(), but for queries that outlive the caller, such as the
+  // inserts handed off to the insert workers. Each distinct statement is only
+  // ever allocated once.
+  pub fn sql(self, sql: &str) -> &'static str {
+    let sql = self.placeholders(sql);
+    let mut statements = STATEMENTS.lock().unwrap();
+    if let Some(statement) = statements.get(sql.as_str()) {
+      return statement;
+    }
+    let statement: &'static str = Box::leak(sql.into_boxed_str());
+    statements.insert(statement);
+    statement
+  }
+
+  pub fn insert(
+    self,
+    table: &str,
+    columns: &[&str],
+    on_conflict: OnConflict<'_>,
+  ) -> &'static str {
+    let verb = match (self, &on_conflict) {
+      (Dialect::MySql, OnConflict::Ignore(_)) => "INSERT IGNORE",
+      _ => "INSERT",
+    };
+    let mut sql = format!(
+      "{} INTO {} ({}) VALUES ({})",
+      verb,
+      table,
+      columns.join(", "),
+      vec!["?"; columns.len()].join(", ")
+    );
+    let non_key_columns = |keys: &[&str]| -> Vec<String> {
+      columns
+        .iter()
+        .filter(|c| !keys.contains(c))
+        .map(|c| c.to_string())
+        .collect()
+    };
+    match (self, on_conflict) {
+      (_, OnConflict::Fail) | (Dialect::MySql, OnConflict::Ignore(_)) => (),
+      (_, OnConflict::Ignore(keys)) => {
+        sql.push_str(&format!(" ON CONFLICT ({}) DO NOTHING", keys.join(", ")));
+      }
+      (Dialect::MySql, OnConflict::Update(keys)) => {
+        let updates = non_key_columns(keys)
+          .iter()
+          .map(|c| format!("{0} = VALUES({0})", c))
+          .join(", ");
+        sql.push_str(&format!(" ON DUPLICATE KEY UPDATE {}", updates));
+      }
+      (_, OnConflict::Update(keys)) => {
+        let updates = non_key_columns(keys)
+          .iter()
+          .map(|c| format!("{0} = excluded.{0}", c))
+          .join(", ");
+        sql.push_str(&format!(
+          " ON CONFLICT ({}) DO UPDATE SET {}",
+          keys.join(", "),
+          updates
+        ));
+      }
+    }
+    self.sql(&sql)
+  }
+}
+
+// Brings the schema up to date using the migrations for the pool's dialect.
+pub async fn migrate(
+  db: &sqlx::Pool<sqlx::Any>,
+) -> std::result::Result<(), sqlx::migrate::MigrateError> {
+  let migrator = match Dialect::of(db) {
+    Dialect::Sqlite => sqlx::migrate!("migrations/sqlite"),
+    Dialect::MySql => sqlx::migrate!("migrations/mysql"),
+    Dialect::Postgres => sqlx::migrate!("migrations/postgres"),
+  };
+  migrator.run(db).await
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct Move {
   pub move_num: i32,
   pub color: String,
@@ -23,46 +251,329 @@ pub struct Move {
   pub ending_location: String,
   pub captured_piece: String,
   pub capture_score: i32,
+  pub promotion_value: i32,
+  pub checkmate: bool,
+  // Empty when the source doesn't provide SAN.
+  pub san: String,
+  pub uci: String,
+  // Position after the move.
+  pub fen: Option<String>,
+  // Time left on the mover's clock after the move, and time spent on it.
+  pub clock_ms: Option<i64>,
+  pub elapsed_ms: Option<i64>,
+  // Engine evaluation of the position after the move, from white's point of
+  // view; at most one of these is set.
+  pub eval_cp: Option<i32>,
+  pub eval_mate: Option<i32>,
+  // Centipawns the mover lost relative to the position before the move, and
+  // whether that makes it an inaccuracy, mistake or blunder.
+  pub cp_loss: Option<i32>,
+  pub classification: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+// Everything a single piece did over the course of one game. Pieces that
+// survive the game have no captured_ply.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PieceLifecycle {
+  pub color: String,
+  pub piece: String,
+  pub spawn_square: String,
+  // Space-separated squares in the order they were visited, starting with the
+  // spawn square.
+  pub squares_visited: String,
+  pub move_count: i32,
+  // move_num of the capturing move
+  pub captured_ply: Option<i32>,
+  pub captured_by: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct Game {
   pub id: String,
   pub source: String,
   pub source_id: String,
   pub end_time: i64,
+  pub time_control: String,
   pub white_player_id: String,
   pub white_player_name: String,
   pub white_player_rating: i32,
   pub black_player_id: String,
   pub black_player_name: String,
   pub black_player_rating: i32,
+  // Average move accuracy for each side, from 0 to 100, once the game has
+  // engine evaluations.
+  pub white_accuracy: Option<f64>,
+  pub black_accuracy: Option<f64>,
+  // Empty when the opening couldn't be identified.
+  pub eco: String,
+  pub opening: String,
+  // STANDARD_VARIANT, or the lichess Variant header in snake case (e.g.
+  // three_check). Games of unsupported variants are stored without moves.
+  pub variant: String,
+  // FEN of the position the game started from, when it isn't the usual one
+  pub starting_fen: Option<String>,
+  // One of the results below, as in PGN Result headers
+  pub result: String,
+  // How the game ended, e.g. checkmate or timeout; empty when unknown
+  pub termination: String,
 }
 
-pub trait Recordable {
-  fn game(&self) -> Result<Game>;
-  fn moves(&self) -> Result<Vec<Move>>;
+pub const WHITE_WON: &str = "1-0";
+pub const BLACK_WON: &str = "0-1";
+pub const DRAWN: &str = "1/2-1/2";
+pub const UNKNOWN_RESULT: &str = "*";
+
+pub const STANDARD_VARIANT: &str = "standard";
+// The usual starting position with some pieces removed
+pub const ODDS_VARIANT: &str = "odds";
+pub const CHESS960_VARIANT: &str = "chess960";
+
+// Speed categories, using lichess's boundaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeClass {
+  UltraBullet,
+  Bullet,
+  Blitz,
+  Rapid,
+  Classical,
+  Correspondence,
 }
 
-impl Move {
-  pub fn insert_query(
-    self,
-    game_id: String,
-  ) -> sqlx::query::Query<'static, sqlx::Any, sqlx::any::AnyArguments<'static>>
+impl TimeClass {
+  pub const NAMES: [&'static str; 6] =
+    ["ultrabullet", "bullet", "blitz", "rapid", "classical", "correspondence"];
+
+  pub fn name(self) -> &'static str {
+    TimeClass::NAMES[self as usize]
+  }
+
+  pub fn from_name(name: &str) -> Option<TimeClass> {
+    match name {
+      "ultrabullet" => Some(TimeClass::UltraBullet),
+      "bullet" => Some(TimeClass::Bullet),
+      "blitz" => Some(TimeClass::Blitz),
+      "rapid" => Some(TimeClass::Rapid),
+      "classical" => Some(TimeClass::Classical),
+      "correspondence" => Some(TimeClass::Correspondence),
+      _ => None,
+    }
+  }
+
+  // Classifies a PGN TimeControl header, e.g. 180+2, by how long a 40 move
+  // game is expected to take. Correspondence games have no time control (-)
+  // or, on chess.com, a days-per-move one (1/86400).
+  pub fn of(time_control: &str) -> Option<TimeClass> {
+    if time_control == "-" || time_control.contains('/') {
+      return Some(TimeClass::Correspondence);
+    }
+    let (base, increment) = match time_control.split_once('+') {
+      Some((base, increment)) => (base, increment.parse::<u32>().ok()?),
+      None => (time_control, 0),
+    };
+    let estimate = base.parse::<u32>().ok()? + 40 * increment;
+    Some(match estimate {
+      0..=29 => TimeClass::UltraBullet,
+      30..=179 => TimeClass::Bullet,
+      180..=479 => TimeClass::Blitz,
+      480..=1499 => TimeClass::Rapid,
+      _ => TimeClass::Classical,
+    })
+  }
+}
+
+// Names how a game ended from a source's description of it, such as a PGN
+// Termination header ("Time forfeit", "bob won by resignation") or a chess.com
+// result message, and from the final position when it's known. lichess
+// describes checkmates, resignations and every kind of draw all as "Normal",
+// so those are told apart by the result and the final position; draws the
+// position doesn't explain are just "draw". The names returned here are
+// recognized too, so exported games read back the same.
+pub fn termination(
+  description: &str,
+  result: &str,
+  ending: Option<dumbchess::Ending>,
+) -> String {
+  let description = description.to_lowercase().replace('_', " ");
+  let termination = if ending == Some(dumbchess::Ending::Checkmate)
+    || description.contains("checkmate")
   {
-    sqlx::query("INSERT INTO Moves (game_id, move_num, color,
-            moved_piece, starting_location, ending_location, captured_piece, capture_score) 
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-            //ON CONFLICT(game_id, move_num, color) DO NOTHING"
-    )
-            .bind(game_id)
-            .bind(self.move_num)
-            .bind(self.color)
-            .bind(self.moved_piece)
-            .bind(self.starting_location)
-            .bind(self.ending_location)
-            .bind(self.captured_piece)
-            .bind(self.capture_score)
+    "checkmate"
+  } else if description.contains("resignation") {
+    "resignation"
+  } else if description.contains("abandon") {
+    "abandonment"
+  } else if description.contains("agreement") {
+    "agreement"
+  } else if description.contains("repetition") {
+    "repetition"
+  } else if description.contains("stalemate") {
+    "stalemate"
+  } else if description.contains("insufficient material") {
+    "insufficient_material"
+  } else if description.contains("50-move") || description.contains("fifty") {
+    "fifty_moves"
+  } else if description.contains("time") {
+    "timeout"
+  } else if description.contains("rules infraction") {
+    "rules_infraction"
+  } else if ending == Some(dumbchess::Ending::Stalemate) {
+    "stalemate"
+  } else if ending == Some(dumbchess::Ending::InsufficientMaterial) {
+    "insufficient_material"
+  } else if (description == "normal" || description == "draw")
+    && result == DRAWN
+  {
+    "draw"
+  } else if description == "normal"
+    && (result == WHITE_WON || result == BLACK_WON)
+  {
+    "resignation"
+  } else {
+    ""
+  };
+  termination.to_owned()
+}
+
+// A game translated from one of the sources, with everything needed to write
+// it out. Records own their moves rather than streaming them: a game is a few
+// hundred moves at most, and its lifecycles and opening aren't known until the
+// last move has been played, so nothing can be written before then anyway.
+// Ingest memory is bounded by how many records are in flight, not by game
+// length.
+pub struct GameRecord {
+  pub game: Game,
+  pub moves: Vec<Move>,
+  pub lifecycles: Vec<PieceLifecycle>,
+}
+
+impl GameRecord {
+  // Consumes the record, returning every query needed to write it. The game
+  // comes first, since the other rows refer to it.
+  pub fn insert_queries(self, dialect: Dialect) -> Vec<Query> {
+    let game_id = self.game.id.clone();
+    let mut inserts = self.game.player_queries(dialect);
+    inserts.insert(0, self.game.insert_query(dialect));
+    inserts.extend(
+      self.moves.into_iter().map(|m| m.insert_query(dialect, game_id.clone())),
+    );
+    inserts.extend(
+      self
+        .lifecycles
+        .into_iter()
+        .map(|l| l.insert_query(dialect, game_id.clone())),
+    );
+    inserts
+  }
+}
+
+impl Move {
+  pub fn insert_query(self, dialect: Dialect, game_id: String) -> Query {
+    sqlx::query(dialect.insert(
+      "Moves",
+      &[
+        "game_id",
+        "move_num",
+        "color",
+        "moved_piece",
+        "starting_location",
+        "ending_location",
+        "captured_piece",
+        "capture_score",
+        "promotion_value",
+        "checkmate",
+        "san",
+        "uci",
+        "fen",
+        "clock_ms",
+        "elapsed_ms",
+        "eval_cp",
+        "eval_mate",
+        "cp_loss",
+        "classification",
+      ],
+      OnConflict::Ignore(&["game_id", "move_num", "color"]),
+    ))
+    .bind(game_id)
+    .bind(self.move_num)
+    .bind(self.color)
+    .bind(self.moved_piece)
+    .bind(self.starting_location)
+    .bind(self.ending_location)
+    .bind(self.captured_piece)
+    .bind(self.capture_score)
+    .bind(self.promotion_value)
+    .bind(self.checkmate)
+    .bind(self.san)
+    .bind(self.uci)
+    .bind(self.fen)
+    .bind(self.clock_ms)
+    .bind(self.elapsed_ms)
+    .bind(self.eval_cp)
+    .bind(self.eval_mate)
+    .bind(self.cp_loss)
+    .bind(self.classification)
+  }
+
+  pub fn update_eval_query(&self, dialect: Dialect, game_id: &str) -> Query {
+    sqlx::query(dialect.sql(
+      "UPDATE Moves SET eval_cp = ?, eval_mate = ?, cp_loss = ?,
+        classification = ?
+        WHERE game_id = ? AND move_num = ? AND color = ?",
+    ))
+    .bind(self.eval_cp)
+    .bind(self.eval_mate)
+    .bind(self.cp_loss)
+    .bind(self.classification.clone())
+    .bind(game_id.to_owned())
+    .bind(self.move_num)
+    .bind(self.color.clone())
+  }
+
+  // The move in UCI notation. Moves ingested before UCI was recorded fall back
+  // to their squares, which only loses the piece promoted to.
+  pub fn uci_move(&self) -> String {
+    if self.uci.is_empty() {
+      format!("{}{}", self.starting_location, self.ending_location)
+    } else {
+      self.uci.clone()
+    }
+  }
+
+  pub fn eval(&self) -> Option<eval::Eval> {
+    eval::Eval::from_columns(self.eval_cp, self.eval_mate)
+  }
+
+  pub fn set_eval(&mut self, eval: eval::Eval) {
+    let (cp, mate) = eval.to_columns();
+    self.eval_cp = cp;
+    self.eval_mate = mate;
+  }
+}
+
+impl PieceLifecycle {
+  pub fn insert_query(self, dialect: Dialect, game_id: String) -> Query {
+    sqlx::query(dialect.insert(
+      "PieceLifecycles",
+      &[
+        "game_id",
+        "color",
+        "piece",
+        "spawn_square",
+        "squares_visited",
+        "move_count",
+        "captured_ply",
+        "captured_by",
+      ],
+      OnConflict::Ignore(&["game_id", "color", "piece"]),
+    ))
+    .bind(game_id)
+    .bind(self.color)
+    .bind(self.piece)
+    .bind(self.spawn_square)
+    .bind(self.squares_visited)
+    .bind(self.move_count)
+    .bind(self.captured_ply)
+    .bind(self.captured_by)
   }
 }
 
@@ -73,35 +584,796 @@ impl Game {
       source: String::new(),
       source_id: String::new(),
       end_time: 0,
+      time_control: String::new(),
       white_player_id: String::new(),
       white_player_name: String::new(),
       white_player_rating: 0,
       black_player_id: String::new(),
       black_player_name: String::new(),
       black_player_rating: 0,
+      white_accuracy: None,
+      black_accuracy: None,
+      eco: String::new(),
+      opening: String::new(),
+      variant: STANDARD_VARIANT.to_owned(),
+      starting_fen: None,
+      result: UNKNOWN_RESULT.to_owned(),
+      termination: String::new(),
     }
   }
 
-  pub fn insert_query(
-    self,
-  ) -> sqlx::query::Query<'static, sqlx::Any, sqlx::any::AnyArguments<'static>>
-  {
-    sqlx::query(
-      "INSERT INTO Games (id, source, source_id, end_time,
-        white_player_id, white_player_name, white_player_rating,
-        black_player_id, black_player_name, black_player_rating) 
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-      //ON CONFLICT(source, id) DO NOTHING",
-    )
+  // The color that won, if either did.
+  pub fn winner(&self) -> Option<&'static str> {
+    match self.result.as_str() {
+      WHITE_WON => Some("white"),
+      BLACK_WON => Some("black"),
+      _ => None,
+    }
+  }
+
+  // Whether the game's moves can be replayed to track its pieces.
+  pub fn is_replayable(&self) -> bool {
+    self.variant == STANDARD_VARIANT || self.variant == ODDS_VARIANT
+  }
+
+  pub fn time_class(&self) -> Option<TimeClass> {
+    TimeClass::of(&self.time_control)
+  }
+
+  // Records each player's rating going into the game, in the game's time
+  // class. Unrated players and unrecognized time controls are left out.
+  pub fn rating_queries(&self, dialect: Dialect) -> Vec<Query> {
+    let time_class = match self.time_class() {
+      Some(time_class) => time_class,
+      None => return Vec::new(),
+    };
+    [
+      (&self.white_player_id, self.white_player_rating),
+      (&self.black_player_id, self.black_player_rating),
+    ]
+    .iter()
+    .filter(|(_, rating)| *rating > 0)
+    .map(|(id, rating)| {
+      sqlx::query(dialect.insert(
+        "PlayerRatings",
+        &["source", "player_id", "time_class", "game_id", "rated_at", "rating"],
+        OnConflict::Ignore(&["source", "player_id", "time_class", "game_id"]),
+      ))
+      .bind(self.source.clone())
+      .bind(id.to_string())
+      .bind(time_class.name())
+      .bind(self.id.clone())
+      .bind(self.end_time)
+      .bind(*rating)
+    })
+    .collect()
+  }
+
+  // Records both players' accounts and the usernames they played under.
+  pub fn player_queries(&self, dialect: Dialect) -> Vec<Query> {
+    let mut queries = Vec::new();
+    for (id, name) in [
+      (&self.white_player_id, &self.white_player_name),
+      (&self.black_player_id, &self.black_player_name),
+    ]
+    .iter()
+    {
+      queries.push(
+        sqlx::query(dialect.insert(
+          "Players",
+          &["source", "external_id"],
+          OnConflict::Ignore(&["source", "external_id"]),
+        ))
+        .bind(self.source.clone())
+        .bind(id.to_string()),
+      );
+      queries.push(
+        sqlx::query(upsert_username_sql(dialect))
+          .bind(self.source.clone())
+          .bind(id.to_string())
+          .bind(name.to_string())
+          .bind(self.end_time)
+          .bind(self.end_time),
+      );
+    }
+    queries.extend(self.rating_queries(dialect));
+    queries
+  }
+
+  pub fn insert_query(self, dialect: Dialect) -> Query {
+    sqlx::query(dialect.insert(
+      "Games",
+      &[
+        "id",
+        "source",
+        "source_id",
+        "end_time",
+        "time_control",
+        "white_player_id",
+        "white_player_name",
+        "white_player_rating",
+        "black_player_id",
+        "black_player_name",
+        "black_player_rating",
+        "white_accuracy",
+        "black_accuracy",
+        "eco",
+        "opening",
+        "variant",
+        "starting_fen",
+        "result",
+        "termination",
+      ],
+      OnConflict::Ignore(&["id"]),
+    ))
     .bind(self.id)
     .bind(self.source)
     .bind(self.source_id)
     .bind(self.end_time)
+    .bind(self.time_control)
     .bind(self.white_player_id)
     .bind(self.white_player_name)
     .bind(self.white_player_rating)
     .bind(self.black_player_id)
     .bind(self.black_player_name)
     .bind(self.black_player_rating)
+    .bind(self.white_accuracy)
+    .bind(self.black_accuracy)
+    .bind(self.eco)
+    .bind(self.opening)
+    .bind(self.variant)
+    .bind(self.starting_fen)
+    .bind(self.result)
+    .bind(self.termination)
+  }
+
+  pub fn update_opening_query(&self, dialect: Dialect) -> Query {
+    sqlx::query(
+      dialect.sql("UPDATE Games SET eco = ?, opening = ? WHERE id = ?"),
+    )
+    .bind(self.eco.clone())
+    .bind(self.opening.clone())
+    .bind(self.id.clone())
+  }
+
+  pub fn update_accuracy_query(&self, dialect: Dialect) -> Query {
+    sqlx::query(dialect.sql(
+      "UPDATE Games SET white_accuracy = ?, black_accuracy = ? WHERE id = ?",
+    ))
+    .bind(self.white_accuracy)
+    .bind(self.black_accuracy)
+    .bind(self.id.clone())
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Player {
+  pub source: String,
+  // The source's own identifier for the account.
+  pub id: String,
+  // The most recently seen username.
+  pub name: String,
+  // Set when the account has been linked to a person.
+  pub person_id: Option<String>,
+}
+
+// A real person, linking their accounts across sources.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Person {
+  pub id: String,
+  pub name: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PlayerUsername {
+  pub username: String,
+  pub first_seen: i64,
+  pub last_seen: i64,
+}
+
+// An account's rating going into one game, as reported by the source.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PlayerRating {
+  pub time_class: String,
+  pub game_id: String,
+  // End time of the game
+  pub rated_at: i64,
+  pub rating: i32,
+}
+
+// Restricts which games the read queries below return. Unset fields match
+// everything.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct GameFilter {
+  pub source: Option<String>,
+  // Games where this account played either color.
+  pub player_id: Option<String>,
+  // Unix timestamps; `since` is inclusive and `until` is exclusive.
+  pub since: Option<i64>,
+  pub until: Option<i64>,
+  pub time_control: Option<String>,
+  pub eco: Option<String>,
+  pub variant: Option<String>,
+  pub limit: Option<i64>,
+  pub offset: Option<i64>,
+}
+
+impl GameFilter {
+  // Appends the filter's conditions to a query selecting from Games aliased
+  // as `g`, along with the values to bind for them in order.
+  pub(crate) fn push_conditions(&self, sql: &mut String) -> Vec<Value> {
+    let mut values = Vec::new();
+    if let Some(source) = &self.source {
+      sql.push_str(" AND g.source = ?");
+      values.push(Value::Text(source.clone()));
+    }
+    if let Some(player_id) = &self.player_id {
+      sql.push_str(" AND (g.white_player_id = ? OR g.black_player_id = ?)");
+      values.push(Value::Text(player_id.clone()));
+      values.push(Value::Text(player_id.clone()));
+    }
+    if let Some(since) = self.since {
+      sql.push_str(" AND g.end_time >= ?");
+      values.push(Value::Int(since));
+    }
+    if let Some(until) = self.until {
+      sql.push_str(" AND g.end_time < ?");
+      values.push(Value::Int(until));
+    }
+    if let Some(time_control) = &self.time_control {
+      sql.push_str(" AND g.time_control = ?");
+      values.push(Value::Text(time_control.clone()));
+    }
+    if let Some(eco) = &self.eco {
+      sql.push_str(" AND g.eco = ?");
+      values.push(Value::Text(eco.clone()));
+    }
+    if let Some(variant) = &self.variant {
+      sql.push_str(" AND g.variant = ?");
+      values.push(Value::Text(variant.clone()));
+    }
+    values
+  }
+
+  fn push_page(&self, sql: &mut String, values: &mut Vec<Value>) {
+    if self.limit.is_some() || self.offset.is_some() {
+      sql.push_str(" LIMIT ? OFFSET ?");
+      values.push(Value::Int(self.limit.unwrap_or(i64::MAX)));
+      values.push(Value::Int(self.offset.unwrap_or(0)));
+    }
+  }
+}
+
+// A bind parameter for queries assembled at runtime.
+pub(crate) enum Value {
+  Text(String),
+  Int(i64),
+}
+
+pub(crate) fn bind_all<'q>(
+  mut query: sqlx::query::Query<'q, sqlx::Any, sqlx::any::AnyArguments<'q>>,
+  values: Vec<Value>,
+) -> sqlx::query::Query<'q, sqlx::Any, sqlx::any::AnyArguments<'q>> {
+  for value in values {
+    query = match value {
+      Value::Text(v) => query.bind(v),
+      Value::Int(v) => query.bind(v),
+    };
+  }
+  query
+}
+
+impl Game {
+  pub fn from_row(row: &AnyRow) -> sqlx::Result<Game> {
+    Ok(Game {
+      id: row.try_get("id")?,
+      source: row.try_get("source")?,
+      source_id: row.try_get("source_id")?,
+      end_time: row.try_get("end_time")?,
+      time_control: row.try_get("time_control")?,
+      white_player_id: row.try_get("white_player_id")?,
+      white_player_name: row.try_get("white_player_name")?,
+      white_player_rating: row.try_get("white_player_rating")?,
+      black_player_id: row.try_get("black_player_id")?,
+      black_player_name: row.try_get("black_player_name")?,
+      black_player_rating: row.try_get("black_player_rating")?,
+      white_accuracy: row.try_get("white_accuracy")?,
+      black_accuracy: row.try_get("black_accuracy")?,
+      eco: row.try_get("eco")?,
+      opening: row.try_get("opening")?,
+      variant: row.try_get("variant")?,
+      starting_fen: row.try_get("starting_fen")?,
+      result: row.try_get("result")?,
+      termination: row.try_get("termination")?,
+    })
+  }
+}
+
+impl Move {
+  pub fn from_row(row: &AnyRow) -> sqlx::Result<Move> {
+    Ok(Move {
+      move_num: row.try_get("move_num")?,
+      color: row.try_get("color")?,
+      moved_piece: row.try_get("moved_piece")?,
+      starting_location: row.try_get("starting_location")?,
+      ending_location: row.try_get("ending_location")?,
+      captured_piece: row.try_get("captured_piece")?,
+      capture_score: row.try_get("capture_score")?,
+      promotion_value: row.try_get("promotion_value")?,
+      checkmate: row.try_get("checkmate")?,
+      san: row.try_get("san")?,
+      uci: row.try_get("uci")?,
+      fen: row.try_get("fen")?,
+      clock_ms: row.try_get("clock_ms")?,
+      elapsed_ms: row.try_get("elapsed_ms")?,
+      eval_cp: row.try_get("eval_cp")?,
+      eval_mate: row.try_get("eval_mate")?,
+      cp_loss: row.try_get("cp_loss")?,
+      classification: row.try_get("classification")?,
+    })
+  }
+}
+
+impl PieceLifecycle {
+  pub fn from_row(row: &AnyRow) -> sqlx::Result<PieceLifecycle> {
+    Ok(PieceLifecycle {
+      color: row.try_get("color")?,
+      piece: row.try_get("piece")?,
+      spawn_square: row.try_get("spawn_square")?,
+      squares_visited: row.try_get("squares_visited")?,
+      move_count: row.try_get("move_count")?,
+      captured_ply: row.try_get("captured_ply")?,
+      captured_by: row.try_get("captured_by")?,
+    })
+  }
+}
+
+impl Player {
+  pub fn from_row(row: &AnyRow) -> sqlx::Result<Player> {
+    Ok(Player {
+      source: row.try_get("source")?,
+      id: row.try_get("id")?,
+      name: row.try_get("name")?,
+      person_id: row.try_get("person_id")?,
+    })
+  }
+}
+
+impl Person {
+  pub fn new(name: &str) -> Person {
+    Person { id: uuid::Uuid::new_v4().to_string(), name: name.to_owned() }
+  }
+
+  pub fn from_row(row: &AnyRow) -> sqlx::Result<Person> {
+    Ok(Person { id: row.try_get("id")?, name: row.try_get("name")? })
+  }
+
+  pub fn insert_query(self, dialect: Dialect) -> Query {
+    sqlx::query(dialect.insert(
+      "People",
+      &["id", "name"],
+      OnConflict::Update(&["id"]),
+    ))
+    .bind(self.id)
+    .bind(self.name)
+  }
+}
+
+impl PlayerRating {
+  pub fn from_row(row: &AnyRow) -> sqlx::Result<PlayerRating> {
+    Ok(PlayerRating {
+      time_class: row.try_get("time_class")?,
+      game_id: row.try_get("game_id")?,
+      rated_at: row.try_get("rated_at")?,
+      rating: row.try_get("rating")?,
+    })
+  }
+}
+
+impl PlayerUsername {
+  pub fn from_row(row: &AnyRow) -> sqlx::Result<PlayerUsername> {
+    Ok(PlayerUsername {
+      username: row.try_get("username")?,
+      first_seen: row.try_get("first_seen")?,
+      last_seen: row.try_get("last_seen")?,
+    })
+  }
+}
+
+// Attaches an account to a person. The account doesn't need to have played
+// any ingested games yet.
+pub fn link_account_query(
+  dialect: Dialect,
+  person_id: &str,
+  source: &str,
+  external_id: &str,
+) -> Query {
+  sqlx::query(dialect.insert(
+    "Players",
+    &["source", "external_id", "person_id"],
+    OnConflict::Update(&["source", "external_id"]),
+  ))
+  .bind(source.to_owned())
+  .bind(external_id.to_owned())
+  .bind(person_id.to_owned())
+}
+
+// Detaches an account from a person. Accounts linked to anyone else are left
+// alone.
+pub fn unlink_account_query(
+  dialect: Dialect,
+  person_id: &str,
+  source: &str,
+  external_id: &str,
+) -> Query {
+  sqlx::query(dialect.sql(
+    "UPDATE Players SET person_id = NULL
+      WHERE source = ? AND external_id = ? AND person_id = ?",
+  ))
+  .bind(source.to_owned())
+  .bind(external_id.to_owned())
+  .bind(person_id.to_owned())
+}
+
+// Widens the seen range of an existing username rather than replacing it, so
+// that games can be ingested in any order.
+fn upsert_username_sql(dialect: Dialect) -> &'static str {
+  let insert = "INSERT INTO PlayerUsernames
+    (source, external_id, username, first_seen, last_seen)
+    VALUES (?, ?, ?, ?, ?)";
+  dialect.sql(&match dialect {
+    Dialect::MySql => format!(
+      "{} ON DUPLICATE KEY UPDATE
+        first_seen = LEAST(first_seen, VALUES(first_seen)),
+        last_seen = GREATEST(last_seen, VALUES(last_seen))",
+      insert
+    ),
+    Dialect::Postgres => format!(
+      "{} ON CONFLICT (source, external_id, username) DO UPDATE SET
+        first_seen = LEAST(PlayerUsernames.first_seen, excluded.first_seen),
+        last_seen = GREATEST(PlayerUsernames.last_seen, excluded.last_seen)",
+      insert
+    ),
+    Dialect::Sqlite => format!(
+      "{} ON CONFLICT (source, external_id, username) DO UPDATE SET
+        first_seen = MIN(first_seen, excluded.first_seen),
+        last_seen = MAX(last_seen, excluded.last_seen)",
+      insert
+    ),
+  })
+}
+
+pub async fn game(
+  db: &sqlx::Pool<sqlx::Any>,
+  id: &str,
+) -> sqlx::Result<Option<Game>> {
+  sqlx::query(Dialect::of(db).sql("SELECT * FROM Games WHERE id = ?"))
+    .bind(id.to_owned())
+    .fetch_optional(db)
+    .await?
+    .as_ref()
+    .map(Game::from_row)
+    .transpose()
+}
+
+pub async fn game_by_source_id(
+  db: &sqlx::Pool<sqlx::Any>,
+  source: &str,
+  source_id: &str,
+) -> sqlx::Result<Option<Game>> {
+  sqlx::query(
+    Dialect::of(db)
+      .sql("SELECT * FROM Games WHERE source = ? AND source_id = ?"),
+  )
+  .bind(source.to_owned())
+  .bind(source_id.to_owned())
+  .fetch_optional(db)
+  .await?
+  .as_ref()
+  .map(Game::from_row)
+  .transpose()
+}
+
+// Games matching the filter, most recent first.
+pub async fn games(
+  db: &sqlx::Pool<sqlx::Any>,
+  filter: &GameFilter,
+) -> sqlx::Result<Vec<Game>> {
+  let mut sql = String::from("SELECT g.* FROM Games g WHERE 1 = 1");
+  let mut values = filter.push_conditions(&mut sql);
+  sql.push_str(" ORDER BY g.end_time DESC, g.id");
+  filter.push_page(&mut sql, &mut values);
+
+  let sql = Dialect::of(db).placeholders(&sql);
+  let rows = bind_all(sqlx::query(&sql), values).fetch_all(db).await?;
+  rows.iter().map(Game::from_row).collect()
+}
+
+// Games that haven't been scored for accuracy yet, most recent first.
+pub async fn games_needing_analysis(
+  db: &sqlx::Pool<sqlx::Any>,
+  limit: i64,
+) -> sqlx::Result<Vec<Game>> {
+  let rows = sqlx::query(Dialect::of(db).sql(
+    "SELECT g.* FROM Games g
+      WHERE g.white_accuracy IS NULL AND g.black_accuracy IS NULL
+        AND EXISTS (SELECT 1 FROM Moves m WHERE m.game_id = g.id)
+      ORDER BY g.end_time DESC, g.id LIMIT ?",
+  ))
+  .bind(limit)
+  .fetch_all(db)
+  .await?;
+  rows.iter().map(Game::from_row).collect()
+}
+
+pub async fn games_by_player(
+  db: &sqlx::Pool<sqlx::Any>,
+  player_id: &str,
+) -> sqlx::Result<Vec<Game>> {
+  games(
+    db,
+    &GameFilter { player_id: Some(player_id.to_owned()), ..Default::default() },
+  )
+  .await
+}
+
+pub async fn games_in_time_range(
+  db: &sqlx::Pool<sqlx::Any>,
+  since: i64,
+  until: i64,
+) -> sqlx::Result<Vec<Game>> {
+  games(
+    db,
+    &GameFilter {
+      since: Some(since),
+      until: Some(until),
+      ..Default::default()
+    },
+  )
+  .await
+}
+
+pub async fn moves_for_game(
+  db: &sqlx::Pool<sqlx::Any>,
+  game_id: &str,
+) -> sqlx::Result<Vec<Move>> {
+  let rows = sqlx::query(
+    Dialect::of(db)
+      .sql("SELECT * FROM Moves WHERE game_id = ? ORDER BY move_num"),
+  )
+  .bind(game_id.to_owned())
+  .fetch_all(db)
+  .await?;
+  rows.iter().map(Move::from_row).collect()
+}
+
+pub async fn lifecycles_for_game(
+  db: &sqlx::Pool<sqlx::Any>,
+  game_id: &str,
+) -> sqlx::Result<Vec<PieceLifecycle>> {
+  let rows = sqlx::query(
+    Dialect::of(db).sql("SELECT * FROM PieceLifecycles WHERE game_id = ?"),
+  )
+  .bind(game_id.to_owned())
+  .fetch_all(db)
+  .await?;
+  rows.iter().map(PieceLifecycle::from_row).collect()
+}
+
+// Every capture made by the given piece identity (e.g. "knight b") in games
+// matching the filter, paired with the ID of the game it happened in. With a
+// player_id set, only that player's captures are returned.
+pub async fn captures_by_piece(
+  db: &sqlx::Pool<sqlx::Any>,
+  piece: &str,
+  filter: &GameFilter,
+) -> sqlx::Result<Vec<(String, Move)>> {
+  let mut sql = String::from(
+    "SELECT m.* FROM Moves m JOIN Games g ON m.game_id = g.id
+      WHERE m.moved_piece = ? AND m.captured_piece <> ''",
+  );
+  let mut values = vec![Value::Text(piece.to_owned())];
+  if let Some(player_id) = &filter.player_id {
+    sql.push_str(
+      " AND ((m.color = 'white' AND g.white_player_id = ?)
+        OR (m.color = 'black' AND g.black_player_id = ?))",
+    );
+    values.push(Value::Text(player_id.clone()));
+    values.push(Value::Text(player_id.clone()));
+  }
+  values.extend(
+    GameFilter { player_id: None, ..filter.clone() }.push_conditions(&mut sql),
+  );
+  sql.push_str(" ORDER BY g.end_time, m.game_id, m.move_num");
+  filter.push_page(&mut sql, &mut values);
+
+  let sql = Dialect::of(db).placeholders(&sql);
+  let rows = bind_all(sqlx::query(&sql), values).fetch_all(db).await?;
+  rows
+    .iter()
+    .map(|row| Ok((row.try_get("game_id")?, Move::from_row(row)?)))
+    .collect()
+}
+
+// Selects Players aliased as `p` along with each account's latest username.
+const PLAYER_COLUMNS: &str = "p.source, p.external_id AS id, p.person_id,
+  COALESCE((SELECT u.username FROM PlayerUsernames u
+    WHERE u.source = p.source AND u.external_id = p.external_id
+    ORDER BY u.last_seen DESC LIMIT 1), p.external_id) AS name";
+
+pub async fn players(
+  db: &sqlx::Pool<sqlx::Any>,
+  limit: i64,
+  offset: i64,
+) -> sqlx::Result<Vec<Player>> {
+  let rows = sqlx::query(Dialect::of(db).sql(&format!(
+    "SELECT {} FROM Players p
+      ORDER BY p.source, p.external_id LIMIT ? OFFSET ?",
+    PLAYER_COLUMNS
+  )))
+  .bind(limit)
+  .bind(offset)
+  .fetch_all(db)
+  .await?;
+  rows.iter().map(Player::from_row).collect()
+}
+
+pub async fn player(
+  db: &sqlx::Pool<sqlx::Any>,
+  source: &str,
+  external_id: &str,
+) -> sqlx::Result<Option<Player>> {
+  sqlx::query(Dialect::of(db).sql(&format!(
+    "SELECT {} FROM Players p WHERE p.source = ? AND p.external_id = ?",
+    PLAYER_COLUMNS
+  )))
+  .bind(source.to_owned())
+  .bind(external_id.to_owned())
+  .fetch_optional(db)
+  .await?
+  .as_ref()
+  .map(Player::from_row)
+  .transpose()
+}
+
+// Usernames the account has played under, most recent first.
+pub async fn username_history(
+  db: &sqlx::Pool<sqlx::Any>,
+  source: &str,
+  external_id: &str,
+) -> sqlx::Result<Vec<PlayerUsername>> {
+  let rows = sqlx::query(Dialect::of(db).sql(
+    "SELECT username, first_seen, last_seen FROM PlayerUsernames
+      WHERE source = ? AND external_id = ? ORDER BY last_seen DESC",
+  ))
+  .bind(source.to_owned())
+  .bind(external_id.to_owned())
+  .fetch_all(db)
+  .await?;
+  rows.iter().map(PlayerUsername::from_row).collect()
+}
+
+// An account's ratings over time, oldest first, optionally in just one time
+// class.
+pub async fn rating_history(
+  db: &sqlx::Pool<sqlx::Any>,
+  source: &str,
+  player_id: &str,
+  time_class: Option<TimeClass>,
+) -> sqlx::Result<Vec<PlayerRating>> {
+  let mut sql = String::from(
+    "SELECT time_class, game_id, rated_at, rating FROM PlayerRatings
+      WHERE source = ? AND player_id = ?",
+  );
+  let mut values =
+    vec![Value::Text(source.to_owned()), Value::Text(player_id.to_owned())];
+  if let Some(time_class) = time_class {
+    sql.push_str(" AND time_class = ?");
+    values.push(Value::Text(time_class.name().to_owned()));
+  }
+  sql.push_str(" ORDER BY rated_at, game_id");
+
+  let sql = Dialect::of(db).placeholders(&sql);
+  let rows = bind_all(sqlx::query(&sql), values).fetch_all(db).await?;
+  rows.iter().map(PlayerRating::from_row).collect()
+}
+
+// An account's rating in a time class as of a Unix timestamp: the rating it
+// took into the first game ending after then, which it held since its
+// previous game. Falls back to the rating taken into the last game ending at
+// or before then, for accounts that haven't played since.
+pub async fn rating_at(
+  db: &sqlx::Pool<sqlx::Any>,
+  source: &str,
+  player_id: &str,
+  time_class: TimeClass,
+  at: i64,
+) -> sqlx::Result<Option<i32>> {
+  let dialect = Dialect::of(db);
+  for sql in [
+    "SELECT rating FROM PlayerRatings
+      WHERE source = ? AND player_id = ? AND time_class = ? AND rated_at > ?
+      ORDER BY rated_at, game_id LIMIT 1",
+    "SELECT rating FROM PlayerRatings
+      WHERE source = ? AND player_id = ? AND time_class = ? AND rated_at <= ?
+      ORDER BY rated_at DESC, game_id DESC LIMIT 1",
+  ]
+  .iter()
+  {
+    let row = sqlx::query(dialect.sql(sql))
+      .bind(source.to_owned())
+      .bind(player_id.to_owned())
+      .bind(time_class.name())
+      .bind(at)
+      .fetch_optional(db)
+      .await?;
+    if let Some(row) = row {
+      return row.try_get("rating").map(Some);
+    }
+  }
+  Ok(None)
+}
+
+pub async fn person_by_name(
+  db: &sqlx::Pool<sqlx::Any>,
+  name: &str,
+) -> sqlx::Result<Option<Person>> {
+  sqlx::query(Dialect::of(db).sql("SELECT * FROM People WHERE name = ?"))
+    .bind(name.to_owned())
+    .fetch_optional(db)
+    .await?
+    .as_ref()
+    .map(Person::from_row)
+    .transpose()
+}
+
+pub async fn accounts_for_person(
+  db: &sqlx::Pool<sqlx::Any>,
+  person_id: &str,
+) -> sqlx::Result<Vec<Player>> {
+  let rows = sqlx::query(Dialect::of(db).sql(&format!(
+    "SELECT {} FROM Players p WHERE p.person_id = ?
+      ORDER BY p.source, p.external_id",
+    PLAYER_COLUMNS
+  )))
+  .bind(person_id.to_owned())
+  .fetch_all(db)
+  .await?;
+  rows.iter().map(Player::from_row).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dumbchess::Ending;
+
+  #[test]
+  fn termination_names() {
+    let stalemate = Some(Ending::Stalemate);
+    let insufficient = Some(Ending::InsufficientMaterial);
+    // lichess calls every draw that isn't on time "Normal".
+    assert_eq!(termination("Normal", DRAWN, stalemate), "stalemate");
+    assert_eq!(
+      termination("Normal", DRAWN, insufficient),
+      "insufficient_material"
+    );
+    assert_eq!(termination("Normal", DRAWN, None), "draw");
+    assert_eq!(termination("Normal", WHITE_WON, None), "resignation");
+    assert_eq!(
+      termination("Normal", BLACK_WON, Some(Ending::Checkmate)),
+      "checkmate"
+    );
+    assert_eq!(termination("Time forfeit", DRAWN, None), "timeout");
+    assert_eq!(
+      termination("Game drawn by agreement", DRAWN, None),
+      "agreement"
+    );
+    assert_eq!(termination("", DRAWN, None), "");
+    // Stored names read back the same.
+    for name in
+      ["draw", "stalemate", "insufficient_material", "agreement"].iter()
+    {
+      assert_eq!(termination(name, DRAWN, None), *name);
+    }
   }
 }
@@ -1,5 +1,4 @@
-use std::collections::HashMap;
-
+use itertools::Itertools;
 use thiserror::Error as ThisError;
 
 use crate::db;
@@ -10,14 +9,29 @@ pub enum Error {
   PieceNotFound(Square),
   #[error("en passant capture not found on encoded square: {0}")]
   EnPassantPieceNotFound(Square),
+  #[error("no legal move matches SAN: {0}")]
+  InvalidSan(String),
+  #[error("illegal move: {0}")]
+  IllegalMove(String),
+  #[error("not the starting position with pieces removed: {0}")]
+  UnsupportedPosition(String),
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Clone)]
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
 enum Color {
   White,
   Black,
 }
 
+impl Color {
+  fn opponent(self) -> Color {
+    match self {
+      Color::White => Color::Black,
+      Color::Black => Color::White,
+    }
+  }
+}
+
 impl std::fmt::Display for Color {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     write!(
@@ -33,7 +47,7 @@ impl std::fmt::Display for Color {
 
 //#[derive(Debug, Hash, Eq, PartialEq, Clone)]
 //struct Square(char);
-#[derive(Debug, Hash, Eq, PartialEq, Clone)]
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
 pub enum Square {
   A1,
   B1,
@@ -101,6 +115,113 @@ pub enum Square {
   H8,
 }
 
+// Every square, in Square::index order.
+const ALL_SQUARES: [Square; 64] = [
+  Square::A1,
+  Square::B1,
+  Square::C1,
+  Square::D1,
+  Square::E1,
+  Square::F1,
+  Square::G1,
+  Square::H1,
+  Square::A2,
+  Square::B2,
+  Square::C2,
+  Square::D2,
+  Square::E2,
+  Square::F2,
+  Square::G2,
+  Square::H2,
+  Square::A3,
+  Square::B3,
+  Square::C3,
+  Square::D3,
+  Square::E3,
+  Square::F3,
+  Square::G3,
+  Square::H3,
+  Square::A4,
+  Square::B4,
+  Square::C4,
+  Square::D4,
+  Square::E4,
+  Square::F4,
+  Square::G4,
+  Square::H4,
+  Square::A5,
+  Square::B5,
+  Square::C5,
+  Square::D5,
+  Square::E5,
+  Square::F5,
+  Square::G5,
+  Square::H5,
+  Square::A6,
+  Square::B6,
+  Square::C6,
+  Square::D6,
+  Square::E6,
+  Square::F6,
+  Square::G6,
+  Square::H6,
+  Square::A7,
+  Square::B7,
+  Square::C7,
+  Square::D7,
+  Square::E7,
+  Square::F7,
+  Square::G7,
+  Square::H7,
+  Square::A8,
+  Square::B8,
+  Square::C8,
+  Square::D8,
+  Square::E8,
+  Square::F8,
+  Square::G8,
+  Square::H8,
+];
+
+// chess.com encodes each square of a move list as one of these characters,
+// in Square::index order.
+const MOVE_LIST_CHARS: &str =
+  "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!?";
+
+impl Square {
+  // Squares are numbered from 0 (a1) to 63 (h8), rank by rank.
+  fn index(self) -> usize {
+    self as usize
+  }
+
+  fn from_index(index: usize) -> Square {
+    ALL_SQUARES[index]
+  }
+
+  // Parses a square's name, e.g. "e4".
+  pub fn parse(name: &[u8]) -> Option<Square> {
+    match name {
+      [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Some(Square::from_index(
+        (rank - b'1') as usize * 8 + (file - b'a') as usize,
+      )),
+      _ => None,
+    }
+  }
+
+  // Decodes a square from a chess.com move list.
+  pub fn from_move_list_char(c: char) -> Option<Square> {
+    MOVE_LIST_CHARS.find(c).map(Square::from_index)
+  }
+
+  fn file(self) -> usize {
+    self.index() % 8
+  }
+
+  fn rank(self) -> usize {
+    self.index() / 8
+  }
+}
+
 impl std::fmt::Display for Square {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     let s = match self {
@@ -173,93 +294,172 @@ impl std::fmt::Display for Square {
   }
 }
 
-impl From<char> for &Square {
-  fn from(c: char) -> Self {
-    match c {
-      'a' => &Square::A1,
-      'b' => &Square::B1,
-      'c' => &Square::C1,
-      'd' => &Square::D1,
-      'e' => &Square::E1,
-      'f' => &Square::F1,
-      'g' => &Square::G1,
-      'h' => &Square::H1,
-      'i' => &Square::A2,
-      'j' => &Square::B2,
-      'k' => &Square::C2,
-      'l' => &Square::D2,
-      'm' => &Square::E2,
-      'n' => &Square::F2,
-      'o' => &Square::G2,
-      'p' => &Square::H2,
-      'q' => &Square::A3,
-      'r' => &Square::B3,
-      's' => &Square::C3,
-      't' => &Square::D3,
-      'u' => &Square::E3,
-      'v' => &Square::F3,
-      'w' => &Square::G3,
-      'x' => &Square::H3,
-      'y' => &Square::A4,
-      'z' => &Square::B4,
-      'A' => &Square::C4,
-      'B' => &Square::D4,
-      'C' => &Square::E4,
-      'D' => &Square::F4,
-      'E' => &Square::G4,
-      'F' => &Square::H4,
-      'G' => &Square::A5,
-      'H' => &Square::B5,
-      'I' => &Square::C5,
-      'J' => &Square::D5,
-      'K' => &Square::E5,
-      'L' => &Square::F5,
-      'M' => &Square::G5,
-      'N' => &Square::H5,
-      'O' => &Square::A6,
-      'P' => &Square::B6,
-      'Q' => &Square::C6,
-      'R' => &Square::D6,
-      'S' => &Square::E6,
-      'T' => &Square::F6,
-      'U' => &Square::G6,
-      'V' => &Square::H6,
-      'W' => &Square::A7,
-      'X' => &Square::B7,
-      'Y' => &Square::C7,
-      'Z' => &Square::D7,
-      '0' => &Square::E7,
-      '1' => &Square::F7,
-      '2' => &Square::G7,
-      '3' => &Square::H7,
-      '4' => &Square::A8,
-      '5' => &Square::B8,
-      '6' => &Square::C8,
-      '7' => &Square::D8,
-      '8' => &Square::E8,
-      '9' => &Square::F8,
-      '!' => &Square::G8,
-      '?' => &Square::H8,
-      c => unreachable!(format!("unrecognized board char: {}", c)),
+// Identities of each side's pieces, in the order they are set up on the
+// starting board.
+pub const PIECES: [&str; 16] = [
+  "rook a", "knight b", "bishop c", "queen d", "king e", "bishop f",
+  "knight g", "rook h", "pawn a", "pawn b", "pawn c", "pawn d", "pawn e",
+  "pawn f", "pawn g", "pawn h",
+];
+
+// Index into PIECES of the king.
+const KING: usize = 4;
+
+// Where each piece in PIECES starts, for white and then black.
+const STARTING_SQUARES: [[Square; 16]; 2] = [
+  [
+    Square::A1,
+    Square::B1,
+    Square::C1,
+    Square::D1,
+    Square::E1,
+    Square::F1,
+    Square::G1,
+    Square::H1,
+    Square::A2,
+    Square::B2,
+    Square::C2,
+    Square::D2,
+    Square::E2,
+    Square::F2,
+    Square::G2,
+    Square::H2,
+  ],
+  [
+    Square::A8,
+    Square::B8,
+    Square::C8,
+    Square::D8,
+    Square::E8,
+    Square::F8,
+    Square::G8,
+    Square::H8,
+    Square::A7,
+    Square::B7,
+    Square::C7,
+    Square::D7,
+    Square::E7,
+    Square::F7,
+    Square::G7,
+    Square::H7,
+  ],
+];
+
+// What a piece currently moves like. This only differs from its identity in
+// PIECES once a pawn promotes.
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
+pub enum Role {
+  Pawn,
+  Knight,
+  Bishop,
+  Rook,
+  Queen,
+  King,
+}
+
+// Role of each piece in PIECES at the start of the game.
+const STARTING_ROLES: [Role; 16] = [
+  Role::Rook,
+  Role::Knight,
+  Role::Bishop,
+  Role::Queen,
+  Role::King,
+  Role::Bishop,
+  Role::Knight,
+  Role::Rook,
+  Role::Pawn,
+  Role::Pawn,
+  Role::Pawn,
+  Role::Pawn,
+  Role::Pawn,
+  Role::Pawn,
+  Role::Pawn,
+  Role::Pawn,
+];
+
+impl Role {
+  pub fn value(self) -> i32 {
+    match self {
+      Role::Pawn => 1,
+      Role::Knight => 3,
+      Role::Bishop => 3,
+      Role::Rook => 5,
+      Role::Queen => 9,
+      Role::King => 0,
+    }
+  }
+
+  // The lowercase letter UCI and FEN use for the role.
+  pub fn letter(self) -> char {
+    match self {
+      Role::Pawn => 'p',
+      Role::Knight => 'n',
+      Role::Bishop => 'b',
+      Role::Rook => 'r',
+      Role::Queen => 'q',
+      Role::King => 'k',
+    }
+  }
+
+  // Parses the lowercase letter UCI uses for a promotion piece.
+  pub fn from_letter(letter: char) -> Option<Role> {
+    Role::from_san(letter.to_ascii_uppercase() as u8)
+  }
+
+  // Parses the uppercase letter SAN uses for a piece.
+  fn from_san(letter: u8) -> Option<Role> {
+    match letter {
+      b'N' => Some(Role::Knight),
+      b'B' => Some(Role::Bishop),
+      b'R' => Some(Role::Rook),
+      b'Q' => Some(Role::Queen),
+      b'K' => Some(Role::King),
+      _ => None,
     }
   }
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Clone)]
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
 struct Piece {
-  piece_type: &'static str,
+  // Index into PIECES
+  id: usize,
   color: Color,
+  role: Role,
   value: i32,
 }
 
 impl Piece {
-  fn with_value(mut self, value: Option<i32>) -> Piece {
-    self.value = match value {
-      None => self.value,
-      Some(v) => v,
-    };
+  fn piece_type(&self) -> &'static str {
+    PIECES[self.id]
+  }
+
+  // Index of the piece's lifecycle on the board.
+  fn lifecycle_index(&self) -> usize {
+    match self.color {
+      Color::White => self.id,
+      Color::Black => PIECES.len() + self.id,
+    }
+  }
+
+  // We use the promotion piece type to set the value of the piece, without
+  // changing the definition of the piece itself. So Pawn on Rank A will
+  // always be Pawn on Rank A (so we have continuity in our points tracking),
+  // but if it gets promoted to a queen on the board then its capture will be
+  // worth 9.
+  fn promote(mut self, promotion: Option<Role>) -> Piece {
+    if let Some(role) = promotion {
+      self.role = role;
+      self.value = role.value();
+    }
     self
   }
+
+  fn fen_char(&self) -> char {
+    match self.color {
+      Color::White => self.role.letter().to_ascii_uppercase(),
+      Color::Black => self.role.letter(),
+    }
+  }
 }
 
 impl std::fmt::Display for Piece {
@@ -267,166 +467,744 @@ impl std::fmt::Display for Piece {
     if f.alternate() {
       write!(f, "{} ", self.color)?;
     }
-    write!(f, "{}", self.piece_type)
+    write!(f, "{}", self.piece_type())
+  }
+}
+
+// Tracks where a single piece has been over the course of a game.
+#[derive(Clone)]
+struct Lifecycle {
+  spawn_square: Square,
+  squares_visited: Vec<Square>,
+  move_count: i32,
+  // Move number on which the piece was captured, and the piece that did it
+  captured: Option<(i32, Piece)>,
+}
+
+impl Lifecycle {
+  fn spawn(square: Square) -> Lifecycle {
+    Lifecycle {
+      spawn_square: square,
+      squares_visited: vec![square],
+      move_count: 0,
+      captured: None,
+    }
+  }
+}
+
+type Squares = [Option<Piece>; 64];
+
+// Whether the piece on `from` attacks `to`, ignoring whose turn it is and
+// whether the move would leave its own king in check.
+fn attacks(squares: &Squares, from: Square, to: Square) -> bool {
+  let piece = match squares[from.index()] {
+    Some(piece) => piece,
+    None => return false,
+  };
+  let df = to.file() as i32 - from.file() as i32;
+  let dr = to.rank() as i32 - from.rank() as i32;
+  match piece.role {
+    Role::Pawn => df.abs() == 1 && dr == forward(piece.color),
+    Role::Knight => {
+      (df.abs(), dr.abs()) == (1, 2) || (df.abs(), dr.abs()) == (2, 1)
+    }
+    Role::King => df.abs().max(dr.abs()) == 1,
+    Role::Bishop => {
+      df.abs() == dr.abs() && df != 0 && path_clear(squares, from, to)
+    }
+    Role::Rook => (df == 0) != (dr == 0) && path_clear(squares, from, to),
+    Role::Queen => {
+      (df.abs() == dr.abs() || df == 0 || dr == 0)
+        && from != to
+        && path_clear(squares, from, to)
+    }
+  }
+}
+
+// Whether every square strictly between two squares on a line is empty.
+fn path_clear(squares: &Squares, from: Square, to: Square) -> bool {
+  let df = (to.file() as i32 - from.file() as i32).signum();
+  let dr = (to.rank() as i32 - from.rank() as i32).signum();
+  let step = dr * 8 + df;
+  let mut index = from.index() as i32 + step;
+  while index != to.index() as i32 {
+    if squares[index as usize].is_some() {
+      return false;
+    }
+    index += step;
+  }
+  true
+}
+
+fn attacked(squares: &Squares, square: Square, by: Color) -> bool {
+  ALL_SQUARES.iter().any(|&from| {
+    matches!(squares[from.index()], Some(p) if p.color == by)
+      && attacks(squares, from, square)
+  })
+}
+
+// Direction pawns of a color advance in, in ranks.
+fn forward(color: Color) -> i32 {
+  match color {
+    Color::White => 1,
+    Color::Black => -1,
   }
 }
 
+// A position that ends the game on the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ending {
+  Checkmate,
+  Stalemate,
+  // Neither side has the pieces left to mate, whatever the other plays
+  InsufficientMaterial,
+}
+
+// Castling rights, indexed by color and then kingside before queenside.
+type Castling = [[bool; 2]; 2];
+
+// Corner squares holding each side's castling rooks, in Castling order.
+const ROOK_CORNERS: [[Square; 2]; 2] =
+  [[Square::H1, Square::A1], [Square::H8, Square::A8]];
+
 #[derive(Clone)]
 pub struct Board {
-  piece_map: HashMap<Square, Piece>,
-  last_move: Option<(&'static str, Square, Square)>,
+  // Indexed by Square::index
+  squares: Squares,
+  // Indexed by Piece::lifecycle_index: white's pieces in PIECES order, then
+  // black's.
+  lifecycles: Vec<Option<Lifecycle>>,
+  // The piece moved last, and where from and to; needed to spot en passant.
+  last_move: Option<(Piece, Square, Square)>,
   move_num: i32,
+  turn: Color,
+  castling: Castling,
+  // Plies since the last capture or pawn move, for FEN
+  halfmove_clock: u32,
 }
 
 impl Board {
   pub fn starting() -> Board {
-    Board {
-      piece_map: maplit::hashmap! {
-          Square::A1 => Piece { piece_type: "rook a", color: Color::White, value: 5},
-          Square::B1 => Piece { piece_type: "knight b", color: Color::White, value: 3},
-          Square::C1 => Piece { piece_type: "bishop c", color: Color::White, value: 3},
-          Square::D1 => Piece { piece_type: "queen d", color: Color::White, value: 9},
-          Square::E1 => Piece { piece_type: "king e", color: Color::White, value: 0},
-          Square::F1 => Piece { piece_type: "bishop f", color: Color::White, value: 3},
-          Square::G1 => Piece { piece_type: "knight g", color: Color::White, value: 3},
-          Square::H1 => Piece { piece_type: "rook h", color: Color::White, value: 5},
-          Square::A2 => Piece { piece_type: "pawn a", color: Color::White, value: 1},
-          Square::B2 => Piece { piece_type: "pawn b", color: Color::White, value: 1},
-          Square::C2 => Piece { piece_type: "pawn c", color: Color::White, value: 1},
-          Square::D2 => Piece { piece_type: "pawn d", color: Color::White, value: 1},
-          Square::E2 => Piece { piece_type: "pawn e", color: Color::White, value: 1},
-          Square::F2 => Piece { piece_type: "pawn f", color: Color::White, value: 1},
-          Square::G2 => Piece { piece_type: "pawn g", color: Color::White, value: 1},
-          Square::H2 => Piece { piece_type: "pawn h", color: Color::White, value: 1},
-
-          Square::A8 => Piece { piece_type: "rook a", color: Color::Black, value: 5},
-          Square::B8 => Piece { piece_type: "knight b", color: Color::Black, value: 3},
-          Square::C8 => Piece { piece_type: "bishop c", color: Color::Black, value: 3},
-          Square::D8 => Piece { piece_type: "queen d", color: Color::Black, value: 9},
-          Square::E8 => Piece { piece_type: "king e", color: Color::Black, value: 0},
-          Square::F8 => Piece { piece_type: "bishop f", color: Color::Black, value: 3},
-          Square::G8 => Piece { piece_type: "knight g", color: Color::Black, value: 3},
-          Square::H8 => Piece { piece_type: "rook h", color: Color::Black, value: 5},
-          Square::A7 => Piece { piece_type: "pawn a", color: Color::Black, value: 1},
-          Square::B7 => Piece { piece_type: "pawn b", color: Color::Black, value: 1},
-          Square::C7 => Piece { piece_type: "pawn c", color: Color::Black, value: 1},
-          Square::D7 => Piece { piece_type: "pawn d", color: Color::Black, value: 1},
-          Square::E7 => Piece { piece_type: "pawn e", color: Color::Black, value: 1},
-          Square::F7 => Piece { piece_type: "pawn f", color: Color::Black, value: 1},
-          Square::G7 => Piece { piece_type: "pawn g", color: Color::Black, value: 1},
-          Square::H7 => Piece { piece_type: "pawn h", color: Color::Black, value: 1},
-      },
+    let mut board = Board {
+      squares: [None; 64],
+      lifecycles: vec![None; 2 * PIECES.len()],
       last_move: None,
       move_num: 0,
+      turn: Color::White,
+      castling: [[true; 2]; 2],
+      halfmove_clock: 0,
+    };
+    for (color, squares) in
+      [Color::White, Color::Black].iter().zip(STARTING_SQUARES.iter())
+    {
+      for (id, square) in squares.iter().enumerate() {
+        let role = STARTING_ROLES[id];
+        let piece = Piece { id, color: *color, role, value: role.value() };
+        board.squares[square.index()] = Some(piece);
+        board.lifecycles[piece.lifecycle_index()] =
+          Some(Lifecycle::spawn(*square));
+      }
+    }
+    board
+  }
+
+  // Sets up the position in a FEN, as long as every piece in it stands on the
+  // square it starts the game on, as in odds games. Pieces missing from the
+  // position never spawn, so they have no lifecycle.
+  pub fn from_fen(fen: &str) -> Result<Board, Error> {
+    let unsupported = || Error::UnsupportedPosition(fen.to_owned());
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    let (placement, turn, castling) = match fields.as_slice() {
+      [placement, turn, castling, ..] => (*placement, *turn, *castling),
+      _ => return Err(unsupported()),
+    };
+    let mut board = Board::starting();
+    board.squares = [None; 64];
+    board.lifecycles = vec![None; 2 * PIECES.len()];
+
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != 8 {
+      return Err(unsupported());
+    }
+    for (rank, row) in (0..8).rev().zip(ranks) {
+      let mut file = 0;
+      for c in row.chars() {
+        if let Some(empty) = c.to_digit(10) {
+          file += empty as usize;
+          continue;
+        }
+        if file >= 8 {
+          return Err(unsupported());
+        }
+        let color =
+          if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+        let square = Square::from_index(rank * 8 + file);
+        let id = STARTING_SQUARES[color as usize]
+          .iter()
+          .position(|s| *s == square)
+          .filter(|id| STARTING_ROLES[*id].letter() == c.to_ascii_lowercase())
+          .ok_or_else(unsupported)?;
+        let role = STARTING_ROLES[id];
+        let piece = Piece { id, color, role, value: role.value() };
+        board.squares[square.index()] = Some(piece);
+        board.lifecycles[piece.lifecycle_index()] =
+          Some(Lifecycle::spawn(square));
+        file += 1;
+      }
+      if file != 8 {
+        return Err(unsupported());
+      }
+    }
+
+    board.turn = match turn {
+      "w" => Color::White,
+      "b" => Color::Black,
+      _ => return Err(unsupported()),
+    };
+    board.castling = [[false; 2]; 2];
+    for c in castling.chars().filter(|c| *c != '-') {
+      let index = "KQkq".find(c).ok_or_else(unsupported)?;
+      board.castling[index / 2][index % 2] = true;
+    }
+    // No pawn has moved, so there's no en passant square to read.
+    if let [_, _, _, _, halfmove_clock, fullmove] = fields.as_slice() {
+      board.halfmove_clock =
+        halfmove_clock.parse().map_err(|_| unsupported())?;
+      let fullmove: i32 = fullmove.parse().map_err(|_| unsupported())?;
+      board.move_num = 2 * (fullmove.max(1) - 1) + board.turn as i32;
+    } else if board.turn == Color::Black {
+      board.move_num = 1;
+    }
+    Ok(board)
+  }
+
+  // Returns the lifecycle of every piece that started the game, white pieces
+  // first.
+  pub fn lifecycles(&self) -> Vec<db::PieceLifecycle> {
+    [Color::White, Color::Black]
+      .iter()
+      .flat_map(|color| PIECES.iter().map(move |p| (color, p)))
+      .zip(self.lifecycles.iter())
+      .filter_map(|((color, p), l)| {
+        l.as_ref().map(|l| db::PieceLifecycle {
+          color: color.to_string(),
+          piece: p.to_string(),
+          spawn_square: l.spawn_square.to_string(),
+          squares_visited: l.squares_visited.iter().join(" "),
+          move_count: l.move_count,
+          captured_ply: l.captured.as_ref().map(|(ply, _)| *ply),
+          captured_by: l
+            .captured
+            .as_ref()
+            .map(|(_, p)| p.to_string())
+            .unwrap_or_else(|| "".into()),
+        })
+      })
+      .collect()
+  }
+
+  // The square a pawn skipped over with a double move last turn, which an
+  // enemy pawn may capture onto en passant.
+  fn en_passant_square(&self) -> Option<Square> {
+    let (piece, start, end) = self.last_move?;
+    if piece.role == Role::Pawn
+      && start.file() == end.file()
+      && (start.rank() as i32 - end.rank() as i32).abs() == 2
+    {
+      Some(Square::from_index((start.index() + end.index()) / 2))
+    } else {
+      None
     }
   }
 
+  // The position in Forsyth-Edwards Notation. Like most tools, this only
+  // names an en passant square when an enemy pawn is next to the pawn that
+  // double-moved.
+  pub fn fen(&self) -> String {
+    let mut fen = String::new();
+    for rank in (0..8).rev() {
+      let mut empty = 0;
+      for file in 0..8 {
+        match self.squares[rank * 8 + file] {
+          Some(piece) => {
+            if empty > 0 {
+              fen.push_str(&empty.to_string());
+              empty = 0;
+            }
+            fen.push(piece.fen_char());
+          }
+          None => empty += 1,
+        }
+      }
+      if empty > 0 {
+        fen.push_str(&empty.to_string());
+      }
+      if rank > 0 {
+        fen.push('/');
+      }
+    }
+
+    fen.push_str(match self.turn {
+      Color::White => " w ",
+      Color::Black => " b ",
+    });
+
+    let rights: String = self
+      .castling
+      .iter()
+      .flatten()
+      .zip(['K', 'Q', 'k', 'q'].iter())
+      .filter(|(allowed, _)| **allowed)
+      .map(|(_, c)| c)
+      .collect();
+    fen.push_str(if rights.is_empty() { "-" } else { &rights });
+
+    let en_passant = self.en_passant_square().filter(|_| {
+      let (_, _, end) = self.last_move.unwrap();
+      [-1, 1].iter().any(|df| {
+        let file = end.file() as i32 + df;
+        (0..8).contains(&file)
+          && matches!(
+            self.squares[end.rank() * 8 + file as usize],
+            Some(p) if p.role == Role::Pawn && p.color == self.turn
+          )
+      })
+    });
+    match en_passant {
+      Some(square) => fen.push_str(&format!(" {}", square)),
+      None => fen.push_str(" -"),
+    }
+
+    let fullmove = self.move_num / 2 + 1;
+    fen.push_str(&format!(" {} {}", self.halfmove_clock, fullmove));
+    fen
+  }
+
+  // Whether the side to move could move a piece from one square to another.
+  fn can_move(&self, from: Square, to: Square) -> bool {
+    let piece = match self.squares[from.index()] {
+      Some(piece) => piece,
+      None => return false,
+    };
+    let target = self.squares[to.index()];
+    if matches!(target, Some(p) if p.color == piece.color) {
+      return false;
+    }
+    if piece.role != Role::Pawn {
+      return attacks(&self.squares, from, to);
+    }
+    let df = to.file() as i32 - from.file() as i32;
+    let dr = to.rank() as i32 - from.rank() as i32;
+    let home_rank = match piece.color {
+      Color::White => 1,
+      Color::Black => 6,
+    };
+    match (df.abs(), dr * forward(piece.color)) {
+      (0, 1) => target.is_none(),
+      (0, 2) => {
+        from.rank() == home_rank
+          && target.is_none()
+          && path_clear(&self.squares, from, to)
+      }
+      (1, 1) => target.is_some() || self.en_passant_square() == Some(to),
+      _ => false,
+    }
+  }
+
+  // Whether moving from one square to another leaves the mover's own king
+  // safe.
+  fn is_legal(&self, from: Square, to: Square) -> bool {
+    let mut squares = self.squares;
+    let piece = match squares[from.index()].take() {
+      Some(piece) => piece,
+      None => return false,
+    };
+    if piece.role == Role::Pawn && self.en_passant_square() == Some(to) {
+      squares[from.rank() * 8 + to.file()] = None;
+    }
+    squares[to.index()] = Some(piece);
+    let king = ALL_SQUARES.iter().find(|s| {
+      matches!(
+        squares[s.index()],
+        Some(p) if p.role == Role::King && p.color == piece.color
+      )
+    });
+    match king {
+      Some(&king) => !attacked(&squares, king, piece.color.opponent()),
+      None => true,
+    }
+  }
+
+  // Whether the side to move may castle with its king moving from one square
+  // to another: it still has the right, nothing stands between king and rook,
+  // and the king doesn't start in, pass through or land on an attacked
+  // square.
+  fn can_castle(&self, from: Square, to: Square) -> bool {
+    let color = self.turn as usize;
+    let side = match to.file() {
+      6 => 0,
+      2 => 1,
+      _ => return false,
+    };
+    let rook = ROOK_CORNERS[color][side];
+    let passed = Square::from_index((from.index() + to.index()) / 2);
+    from.index() == color * 56 + 4
+      && to.rank() == from.rank()
+      && self.castling[color][side]
+      && matches!(
+        self.squares[rook.index()],
+        Some(p) if p.role == Role::Rook && p.color == self.turn
+      )
+      && path_clear(&self.squares, from, rook)
+      && [from, passed, to]
+        .iter()
+        .all(|s| !attacked(&self.squares, *s, self.turn.opponent()))
+  }
+
+  // Whether the side to move has any legal move. Castling can be left out: a
+  // king that may castle may also just step towards its rook.
+  fn has_legal_move(&self) -> bool {
+    ALL_SQUARES.iter().any(|&from| {
+      matches!(self.squares[from.index()], Some(p) if p.color == self.turn)
+        && ALL_SQUARES
+          .iter()
+          .any(|&to| self.can_move(from, to) && self.is_legal(from, to))
+    })
+  }
+
+  fn in_check(&self) -> bool {
+    ALL_SQUARES.iter().any(|s| {
+      matches!(
+        self.squares[s.index()],
+        Some(p) if p.role == Role::King && p.color == self.turn
+      ) && attacked(&self.squares, *s, self.turn.opponent())
+    })
+  }
+
+  // Whether no sequence of moves could mate either side: only kings are left,
+  // plus at most one knight or bishop, or any number of bishops that all
+  // stand on squares of the same color.
+  fn insufficient_material(&self) -> bool {
+    let mut minors = Vec::new();
+    for square in ALL_SQUARES.iter() {
+      match self.squares[square.index()].map(|p| p.role) {
+        None | Some(Role::King) => (),
+        Some(role @ Role::Knight) | Some(role @ Role::Bishop) => {
+          minors.push((role, (square.file() + square.rank()) % 2))
+        }
+        Some(_) => return false,
+      }
+    }
+    minors.len() <= 1
+      || (minors.iter().all(|(role, _)| *role == Role::Bishop)
+        && minors.iter().map(|(_, shade)| shade).all_equal())
+  }
+
+  // How the game ends in the current position, if it's over.
+  pub fn ending(&self) -> Option<Ending> {
+    if !self.has_legal_move() {
+      Some(if self.in_check() { Ending::Checkmate } else { Ending::Stalemate })
+    } else if self.insufficient_material() {
+      Some(Ending::InsufficientMaterial)
+    } else {
+      None
+    }
+  }
+
+  // Writes a move of the side to move in standard algebraic notation, e.g.
+  // "Nbd7", "exd6", "O-O" or "e8=Q+", for sources that only record where
+  // pieces moved. Fails if the move isn't legal.
+  pub fn san(
+    &self,
+    start: Square,
+    end: Square,
+    promotion: Option<Role>,
+  ) -> Result<String, Error> {
+    let illegal = || {
+      let mut uci = format!("{}{}", start, end);
+      uci.extend(promotion.map(Role::letter));
+      Error::IllegalMove(uci)
+    };
+    let piece = match self.squares[start.index()] {
+      Some(piece) if piece.color == self.turn => piece,
+      _ => return Err(illegal()),
+    };
+    let file_distance = (end.file() as i32 - start.file() as i32).abs();
+    let castles = piece.role == Role::King && file_distance == 2;
+    let legal = if castles {
+      self.can_castle(start, end)
+    } else {
+      self.can_move(start, end) && self.is_legal(start, end)
+    };
+    let promotes =
+      piece.role == Role::Pawn && (end.rank() == 0 || end.rank() == 7);
+    if !legal
+      || promotes != promotion.is_some()
+      || matches!(promotion, Some(Role::Pawn) | Some(Role::King))
+    {
+      return Err(illegal());
+    }
+
+    let start_name = start.to_string();
+    let (file, rank) = start_name.split_at(1);
+    let mut san = String::new();
+    if castles {
+      san.push_str(if end.file() == 6 { "O-O" } else { "O-O-O" });
+    } else if piece.role == Role::Pawn {
+      // A pawn changing files is always a capture, even en passant onto an
+      // empty square.
+      if file_distance != 0 {
+        san.push_str(file);
+        san.push('x');
+      }
+      san.push_str(&end.to_string());
+      if let Some(role) = promotion {
+        san.push('=');
+        san.push(role.letter().to_ascii_uppercase());
+      }
+    } else {
+      san.push(piece.role.letter().to_ascii_uppercase());
+      let rivals: Vec<Square> = ALL_SQUARES
+        .iter()
+        .copied()
+        .filter(|from| {
+          *from != start
+            && matches!(
+              self.squares[from.index()],
+              Some(p) if p.color == piece.color && p.role == piece.role
+            )
+            && self.can_move(*from, end)
+            && self.is_legal(*from, end)
+        })
+        .collect();
+      if rivals.iter().any(|s| s.file() == start.file()) {
+        if rivals.iter().any(|s| s.rank() == start.rank()) {
+          san.push_str(file);
+        }
+        san.push_str(rank);
+      } else if !rivals.is_empty() {
+        san.push_str(file);
+      }
+      if self.squares[end.index()].is_some() {
+        san.push('x');
+      }
+      san.push_str(&end.to_string());
+    }
+
+    let mut after = self.clone();
+    after.make_move(&start, &end, promotion)?;
+    if after.in_check() {
+      san.push(if after.has_legal_move() { '+' } else { '#' });
+    }
+    Ok(san)
+  }
+
+  // Works out which move a SAN string (e.g. "Nbd7", "exd5", "e8=Q+" or
+  // "O-O") describes in the current position.
+  fn resolve_san(
+    &self,
+    san: &str,
+  ) -> Result<(Square, Square, Option<Role>), Error> {
+    let invalid = || Error::InvalidSan(san.to_owned());
+    let text = san.trim_end_matches(&['+', '#', '!', '?'][..]);
+    let back_rank = match self.turn {
+      Color::White => 0,
+      Color::Black => 56,
+    };
+    match text {
+      "O-O" | "0-0" => {
+        return Ok((
+          Square::from_index(back_rank + 4),
+          Square::from_index(back_rank + 6),
+          None,
+        ))
+      }
+      "O-O-O" | "0-0-0" => {
+        return Ok((
+          Square::from_index(back_rank + 4),
+          Square::from_index(back_rank + 2),
+          None,
+        ))
+      }
+      _ => (),
+    }
+
+    let (text, promotion) = match text.split_once('=') {
+      Some((text, role)) => (
+        text,
+        Some(role.bytes().next().and_then(Role::from_san).ok_or_else(invalid)?),
+      ),
+      None => (text, None),
+    };
+    let text = text.as_bytes();
+    let (role, text) = match text.first().copied().and_then(Role::from_san) {
+      Some(role) => (role, &text[1..]),
+      None => (Role::Pawn, text),
+    };
+    if text.len() < 2 {
+      return Err(invalid());
+    }
+    let (hints, to) = text.split_at(text.len() - 2);
+    let to = Square::parse(to).ok_or_else(invalid)?;
+    let (mut file, mut rank) = (None, None);
+    for c in hints {
+      match c {
+        b'a'..=b'h' => file = Some((c - b'a') as usize),
+        b'1'..=b'8' => rank = Some((c - b'1') as usize),
+        b'x' => (),
+        _ => return Err(invalid()),
+      }
+    }
+
+    let candidates: Vec<Square> = ALL_SQUARES
+      .iter()
+      .copied()
+      .filter(|from| {
+        matches!(
+          self.squares[from.index()],
+          Some(p) if p.color == self.turn && p.role == role
+        ) && !matches!(file, Some(f) if from.file() != f)
+          && !matches!(rank, Some(r) if from.rank() != r)
+          && self.can_move(*from, to)
+      })
+      .collect();
+    // Only weed out pinned pieces when the SAN alone is ambiguous, since a
+    // game record's moves are legal.
+    let candidates = if candidates.len() > 1 {
+      candidates.into_iter().filter(|from| self.is_legal(*from, to)).collect()
+    } else {
+      candidates
+    };
+    match candidates.as_slice() {
+      [from] => Ok((*from, to, promotion)),
+      _ => Err(invalid()),
+    }
+  }
+
+  fn record_visit(
+    &mut self,
+    piece: &Piece,
+    square: Square,
+    counts_as_move: bool,
+  ) {
+    if let Some(l) = &mut self.lifecycles[piece.lifecycle_index()] {
+      l.squares_visited.push(square);
+      if counts_as_move {
+        l.move_count += 1;
+      }
+    }
+  }
+
+  // Moves the rook that castles alongside the king.
+  fn castle_rook(&mut self, from: Square, to: Square) -> Result<(), Error> {
+    let rook =
+      self.squares[from.index()].take().ok_or(Error::PieceNotFound(from))?;
+    self.record_visit(&rook, to, false);
+    self.squares[to.index()] = Some(rook);
+    Ok(())
+  }
+
+  // Plays a move given in standard algebraic notation.
+  pub fn make_san_move(&mut self, san: &str) -> Result<db::Move, Error> {
+    let (start, end, promotion) = self.resolve_san(san)?;
+    let mut m = self.make_move(&start, &end, promotion)?;
+    m.san = san.to_owned();
+    m.checkmate = san.ends_with('#');
+    Ok(m)
+  }
+
   pub fn make_move(
     &mut self,
     start: &Square,
     end: &Square,
-    promotion_value: Option<i32>,
+    promotion: Option<Role>,
   ) -> Result<db::Move, Error> {
+    let (start, end) = (*start, *end);
     let move_num = self.move_num;
     self.move_num += 1;
+    let en_passant_square = self.en_passant_square();
     // Fetch the last move. On all exits to this function, set the last move as
     // this move for the next iteration. We need the last move to detect en
     // passant situations.
-    let last_move = std::mem::take(&mut self.last_move);
-    //println!("H5 has: {:?}", self.piece_map.get(&Square::H5));
+    let last_move = self.last_move.take();
 
     // Get the piece at the start location
-    let (_loc, moved_piece) = self
-      .piece_map
-      .remove_entry(start)
-      .ok_or_else(|| Error::PieceNotFound(start.clone()))?;
+    let moved_piece =
+      self.squares[start.index()].take().ok_or(Error::PieceNotFound(start))?;
 
     // Handle regular moves
     // Get the piece at the end location; if there is one, the score is the
     // value of the piece.
     // If this is an en-passant capture, this calculation will be zero but will
     // be updated below.
-    let mut captured_piece = self.piece_map.get(&end).cloned();
+    let mut captured_piece = self.squares[end.index()];
 
     // Handle en passant
     // If the last move was a pawn double-move and this move is a pawn capture
-    // corresponding to that particular pawn double-move, then this was an en
-    // passant capture. The piece moved last move is removed from the board, and
-    // the piece moved this turn scores points.
-    if let Some(last_move) = last_move {
-      if EN_PASSANT_MOVES
-        .get(&(start.clone(), end.clone()))
-        .map(|f| f.0 == last_move.0 && f.1 == last_move.1 && f.2 == last_move.2)
-        == Some(true)
-        && moved_piece.piece_type.starts_with("pawn")
+    // onto the square that pawn skipped over, then this was an en passant
+    // capture. The piece moved last move is removed from the board, and the
+    // piece moved this turn scores points.
+    if let Some((_, _, last_end)) = last_move {
+      if moved_piece.role == Role::Pawn
+        && en_passant_square == Some(end)
+        && start.rank() == last_end.rank()
       {
         // Remove the piece on the last move's end square
         captured_piece = Some(
-          self
-            .piece_map
-            .remove(&last_move.2)
-            .ok_or(Error::EnPassantPieceNotFound(last_move.2))?,
+          self.squares[last_end.index()]
+            .take()
+            .ok_or(Error::EnPassantPieceNotFound(last_end))?,
         );
       }
     }
     let score = captured_piece.as_ref().map(|p| p.value).unwrap_or(0);
+    if let Some(captured) = &captured_piece {
+      if let Some(l) = &mut self.lifecycles[captured.lifecycle_index()] {
+        l.captured = Some((move_num, moved_piece));
+      }
+    }
+    self.record_visit(&moved_piece, end, true);
 
     // Castling is handled here by seeing if we see the king jump 2 squares
     // in one of the possible castling scenarios. If this happens, we need
     // to be sure to update the rook as well, as its movement is implied;
     // failure to do so means that the rook won't be found at its expected
     // square when its moved later.
-    match (moved_piece.clone(), start, end) {
-      // White kingside castle
-      (Piece { piece_type: "king e", .. }, Square::E1, Square::G1) => {
-        self.piece_map.insert(end.clone(), moved_piece.clone());
-        let (_, rook) = self
-          .piece_map
-          .remove_entry(&Square::H1)
-          .ok_or(Error::PieceNotFound(Square::H1))?;
-        self.piece_map.insert(Square::F1, rook);
-      }
-      // White queenside castle
-      (Piece { piece_type: "king e", .. }, Square::E1, Square::C1) => {
-        self.piece_map.insert(end.clone(), moved_piece.clone());
-        let (_, rook) = self
-          .piece_map
-          .remove_entry(&Square::A1)
-          .ok_or(Error::PieceNotFound(Square::A1))?;
-        self.piece_map.insert(Square::D1, rook);
+    self.squares[end.index()] = Some(moved_piece.promote(promotion));
+    if moved_piece.id == KING {
+      match (start, end) {
+        (Square::E1, Square::G1) => self.castle_rook(Square::H1, Square::F1)?,
+        (Square::E1, Square::C1) => self.castle_rook(Square::A1, Square::D1)?,
+        (Square::E8, Square::G8) => self.castle_rook(Square::H8, Square::F8)?,
+        (Square::E8, Square::C8) => self.castle_rook(Square::A8, Square::D8)?,
+        _ => (),
       }
-      // Black kingside castle
-      (Piece { piece_type: "king e", .. }, Square::E8, Square::G8) => {
-        self.piece_map.insert(end.clone(), moved_piece.clone());
-        let (_, rook) = self
-          .piece_map
-          .remove_entry(&Square::H8)
-          .ok_or(Error::PieceNotFound(Square::H8))?;
-        self.piece_map.insert(Square::F8, rook);
-      }
-      // Black queenside castle
-      (Piece { piece_type: "king e", .. }, Square::E8, Square::C8) => {
-        self.piece_map.insert(end.clone(), moved_piece.clone());
-        let (_, rook) = self
-          .piece_map
-          .remove_entry(&Square::A8)
-          .ok_or(Error::PieceNotFound(Square::A8))?;
-        self.piece_map.insert(Square::D8, rook);
-      }
-      // Normal move
-      // We use the promotion piece type to set the value of the piece, without
-      // changing the definition of the piece itself. So Pawn on Rank A will
-      // always be Pawn on Rank A (so we have continuity in our points tracking),
-      // but if it gets promoted to a queen on the board then its capture will be
-      // worth 9.
-      _ => {
-        self
-          .piece_map
-          .insert(end.clone(), moved_piece.clone().with_value(promotion_value));
-        //println!("{} moves to {}", moved_piece, end);
+    }
+
+    // Moving the king gives up castling on both sides; moving a rook off its
+    // corner, or capturing one on it, gives up castling on that side.
+    for (color, corners) in ROOK_CORNERS.iter().enumerate() {
+      for (side, corner) in corners.iter().enumerate() {
+        if start == *corner || end == *corner {
+          self.castling[color][side] = false;
+        }
       }
     }
-    self.last_move = Some((moved_piece.piece_type, start.clone(), end.clone()));
+    if moved_piece.role == Role::King {
+      self.castling[moved_piece.color as usize] = [false; 2];
+    }
+    if moved_piece.role == Role::Pawn || captured_piece.is_some() {
+      self.halfmove_clock = 0;
+    } else {
+      self.halfmove_clock += 1;
+    }
+    self.turn = moved_piece.color.opponent();
+    self.last_move = Some((moved_piece, start, end));
+
+    let mut uci = format!("{}{}", start, end);
+    if let Some(role) = promotion {
+      uci.push(role.letter());
+    }
     // Return the starting piece along with its score
     Ok(db::Move {
       move_num,
@@ -438,42 +1216,18 @@ impl Board {
         .map(|p| p.to_string())
         .unwrap_or_else(|| "".into()),
       capture_score: score,
+      promotion_value: promotion.map(Role::value).unwrap_or(0),
+      checkmate: false,
+      // Callers that know the SAN, clock and engine evaluation fill these in.
+      san: String::new(),
+      uci,
+      fen: Some(self.fen()),
+      clock_ms: None,
+      elapsed_ms: None,
+      eval_cp: None,
+      eval_mate: None,
+      cp_loss: None,
+      classification: None,
     })
   }
 }
-
-lazy_static! {
-  static ref EN_PASSANT_MOVES: HashMap<(Square, Square), (&'static str, Square, Square)> = maplit::hashmap! {
-    (Square::A4, Square::B3) => ("pawn b", Square::B2, Square::B4),
-    (Square::B4, Square::C3) => ("pawn c", Square::C2, Square::C4),
-    (Square::C4, Square::D3) => ("pawn d", Square::D2, Square::D4),
-    (Square::D4, Square::E3) => ("pawn e", Square::E2, Square::E4),
-    (Square::E4, Square::F3) => ("pawn f", Square::F2, Square::F4),
-    (Square::F4, Square::G3) => ("pawn g", Square::G2, Square::G4),
-    (Square::G4, Square::H3) => ("pawn h", Square::H2, Square::H4),
-
-    (Square::B4, Square::A3) => ("pawn a", Square::A2, Square::A4),
-    (Square::C4, Square::B3) => ("pawn b", Square::B2, Square::B4),
-    (Square::D4, Square::C3) => ("pawn c", Square::C2, Square::C4),
-    (Square::E4, Square::D3) => ("pawn d", Square::D2, Square::D4),
-    (Square::F4, Square::E3) => ("pawn e", Square::E2, Square::E4),
-    (Square::G4, Square::F3) => ("pawn f", Square::F2, Square::F4),
-    (Square::H4, Square::G3) => ("pawn g", Square::G2, Square::G4),
-
-    (Square::B5, Square::A6) => ("pawn a", Square::A7, Square::A5),
-    (Square::C5, Square::B6) => ("pawn b", Square::B7, Square::B5),
-    (Square::D5, Square::C6) => ("pawn c", Square::C7, Square::C5),
-    (Square::E5, Square::D6) => ("pawn d", Square::D7, Square::D5),
-    (Square::F5, Square::E6) => ("pawn e", Square::E7, Square::E5),
-    (Square::G5, Square::F6) => ("pawn f", Square::F7, Square::F5),
-    (Square::H5, Square::G6) => ("pawn g", Square::G7, Square::G5),
-
-    (Square::A5, Square::B6) => ("pawn b", Square::B7, Square::B5),
-    (Square::B5, Square::C6) => ("pawn c", Square::C7, Square::C5),
-    (Square::C5, Square::D6) => ("pawn d", Square::D7, Square::D5),
-    (Square::D5, Square::E6) => ("pawn e", Square::E7, Square::E5),
-    (Square::E5, Square::F6) => ("pawn f", Square::F7, Square::F5),
-    (Square::F5, Square::G6) => ("pawn g", Square::G7, Square::G5),
-    (Square::G5, Square::H6) => ("pawn h", Square::H7, Square::H5),
-  };
-}
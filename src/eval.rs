@@ -0,0 +1,181 @@
+use thiserror::Error as ThisError;
+
+use crate::db;
+
+// Evaluations beyond this are treated as this when working out how much a
+// move lost, so that a mate score or a swing between two already-won
+// positions doesn't dwarf every other mistake in the game.
+const MAX_CENTIPAWNS: i32 = 1000;
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+  #[error("unrecognized evaluation: {0}")]
+  InvalidEval(String),
+  #[error("unrecognized move classification: {0}")]
+  InvalidClassification(String),
+}
+
+// An engine's assessment of a position, always from white's point of view.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Eval {
+  Centipawns(i32),
+  // Moves until mate; negative when black is mating.
+  Mate(i32),
+}
+
+impl Eval {
+  pub fn to_centipawns(self) -> i32 {
+    match self {
+      Eval::Centipawns(cp) => cp.clamp(-MAX_CENTIPAWNS, MAX_CENTIPAWNS),
+      Eval::Mate(n) if n < 0 => -MAX_CENTIPAWNS,
+      Eval::Mate(_) => MAX_CENTIPAWNS,
+    }
+  }
+
+  // Splits into the (eval_cp, eval_mate) columns stored on Moves.
+  pub fn to_columns(self) -> (Option<i32>, Option<i32>) {
+    match self {
+      Eval::Centipawns(cp) => (Some(cp), None),
+      Eval::Mate(n) => (None, Some(n)),
+    }
+  }
+
+  pub fn from_columns(cp: Option<i32>, mate: Option<i32>) -> Option<Eval> {
+    match (cp, mate) {
+      (_, Some(n)) => Some(Eval::Mate(n)),
+      (Some(cp), None) => Some(Eval::Centipawns(cp)),
+      (None, None) => None,
+    }
+  }
+}
+
+// Parses the argument of a PGN `[%eval ...]` command: pawns as a decimal
+// (e.g. "-0.35") or a mate distance (e.g. "#4" or "#-2").
+impl std::str::FromStr for Eval {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.split(',').next().unwrap_or_default().trim();
+    let invalid = || Error::InvalidEval(s.to_owned());
+    if let Some(mate) = s.strip_prefix('#') {
+      mate.parse().map(Eval::Mate).map_err(|_| invalid())
+    } else {
+      s.parse::<f64>()
+        .map(|pawns| Eval::Centipawns((pawns * 100.0).round() as i32))
+        .map_err(|_| invalid())
+    }
+  }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Classification {
+  Inaccuracy,
+  Mistake,
+  Blunder,
+}
+
+impl Classification {
+  pub fn from_cp_loss(cp_loss: i32) -> Option<Classification> {
+    match cp_loss {
+      l if l >= 300 => Some(Classification::Blunder),
+      l if l >= 100 => Some(Classification::Mistake),
+      l if l >= 50 => Some(Classification::Inaccuracy),
+      _ => None,
+    }
+  }
+}
+
+impl std::fmt::Display for Classification {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        Classification::Inaccuracy => "inaccuracy",
+        Classification::Mistake => "mistake",
+        Classification::Blunder => "blunder",
+      }
+    )
+  }
+}
+
+impl std::str::FromStr for Classification {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "inaccuracy" => Ok(Classification::Inaccuracy),
+      "mistake" => Ok(Classification::Mistake),
+      "blunder" => Ok(Classification::Blunder),
+      s => Err(Error::InvalidClassification(s.to_owned())),
+    }
+  }
+}
+
+// How much worse the position got for the side that moved, in centipawns.
+pub fn centipawn_loss(before: Eval, after: Eval, mover: &str) -> i32 {
+  let swing = after.to_centipawns() - before.to_centipawns();
+  let loss = if mover == "white" { -swing } else { swing };
+  loss.max(0)
+}
+
+// Fills in cp_loss and classification for every move whose eval, and the eval
+// of the position before it, are known. The starting position counts as
+// level.
+pub fn annotate(moves: &mut [db::Move]) {
+  let mut before = Some(Eval::Centipawns(0));
+  for m in moves.iter_mut() {
+    let after = m.eval();
+    if let (Some(before), Some(after)) = (before, after) {
+      let loss = centipawn_loss(before, after, &m.color);
+      m.cp_loss = Some(loss);
+      m.classification =
+        Classification::from_cp_loss(loss).map(|c| c.to_string());
+    }
+    before = after;
+  }
+}
+
+// Chance of winning from white's point of view, as a percentage, using the
+// same curve as lichess.
+fn win_percent(eval: Eval) -> f64 {
+  let cp = eval.to_centipawns() as f64;
+  50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * cp).exp()) - 1.0)
+}
+
+// How close a move came to keeping the mover's winning chances intact, from
+// 0 to 100.
+pub fn move_accuracy(before: Eval, after: Eval, mover: &str) -> f64 {
+  let (before, after) = if mover == "white" {
+    (win_percent(before), win_percent(after))
+  } else {
+    (100.0 - win_percent(before), 100.0 - win_percent(after))
+  };
+  let drop = (before - after).max(0.0);
+  (103.1668 * (-0.04354 * drop).exp() - 3.1669).clamp(0.0, 100.0)
+}
+
+// Average accuracy of one side's moves, or None if none of them could be
+// scored.
+pub fn accuracy(moves: &[db::Move], color: &str) -> Option<f64> {
+  let mut before = Some(Eval::Centipawns(0));
+  let mut total = 0.0;
+  let mut count = 0;
+  for m in moves {
+    let after = m.eval();
+    if let (Some(before), Some(after)) = (before, after) {
+      if m.color == color {
+        total += move_accuracy(before, after, &m.color);
+        count += 1;
+      }
+    }
+    before = after;
+  }
+  if count == 0 {
+    None
+  } else {
+    Some(total / count as f64)
+  }
+}
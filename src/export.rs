@@ -0,0 +1,742 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::{
+  ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray,
+};
+use arrow_schema::{DataType, Field, Schema};
+use futures::TryStreamExt;
+use sqlx::{any::AnyRow, Row};
+use thiserror::Error as ThisError;
+
+use crate::db::{self, Dialect};
+use crate::dumbchess::{self, Role, Square};
+use crate::eval::Eval;
+use crate::stats;
+
+// Players are read this many at a time when deriving stats for all of them.
+const PLAYER_PAGE_SIZE: i64 = 1000;
+
+// Rows are handed to Parquet in batches of this many.
+const PARQUET_BATCH_SIZE: usize = 8192;
+
+// Movetext lines are wrapped at this width, as the PGN standard asks.
+const PGN_LINE_WIDTH: usize = 80;
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+  #[error("database error: {source}")]
+  Database {
+    #[from]
+    source: sqlx::Error,
+  },
+  #[error("failed to write output: {source}")]
+  Io {
+    #[from]
+    source: std::io::Error,
+  },
+  #[error("failed to write CSV: {source}")]
+  Csv {
+    #[from]
+    source: csv::Error,
+  },
+  #[error("failed to write JSON: {source}")]
+  Json {
+    #[from]
+    source: serde_json::Error,
+  },
+  #[error("failed to write Parquet: {source}")]
+  Parquet {
+    #[from]
+    source: parquet::errors::ParquetError,
+  },
+  #[error("failed to build Parquet batch: {source}")]
+  Arrow {
+    #[from]
+    source: arrow_schema::ArrowError,
+  },
+  #[error("unrecognized {0}: {1}")]
+  UnrecognizedValue(&'static str, String),
+  #[error("illegal move {uci} in game {game_id}")]
+  IllegalMove { game_id: String, uci: String },
+  #[error("invalid starting position {fen} in game {game_id}")]
+  InvalidFen { game_id: String, fen: String },
+  #[error("end time {end_time} out of range in game {game_id}")]
+  InvalidEndTime { game_id: String, end_time: i64 },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Format {
+  Csv,
+  // One JSON object per line
+  Ndjson,
+  Parquet,
+  // Whole games as standard PGN, whatever the table
+  Pgn,
+}
+
+impl std::str::FromStr for Format {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "csv" => Ok(Format::Csv),
+      "ndjson" => Ok(Format::Ndjson),
+      "parquet" => Ok(Format::Parquet),
+      "pgn" => Ok(Format::Pgn),
+      s => Err(Error::UnrecognizedValue("export format", s.to_owned())),
+    }
+  }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Table {
+  Games,
+  Moves,
+  // Per-game piece lifecycles
+  Pieces,
+  // Career totals for each player's pieces, derived by stats::piece_stats
+  PieceStats,
+}
+
+impl std::str::FromStr for Table {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "games" => Ok(Table::Games),
+      "moves" => Ok(Table::Moves),
+      "pieces" => Ok(Table::Pieces),
+      "piece_stats" => Ok(Table::PieceStats),
+      s => Err(Error::UnrecognizedValue("export table", s.to_owned())),
+    }
+  }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Kind {
+  Text,
+  // All integers are exported as 64-bit.
+  Int,
+  Float,
+  Bool,
+}
+
+struct Column {
+  name: &'static str,
+  kind: Kind,
+  nullable: bool,
+}
+
+const fn column(name: &'static str, kind: Kind) -> Column {
+  Column { name, kind, nullable: false }
+}
+
+const fn nullable(name: &'static str, kind: Kind) -> Column {
+  Column { name, kind, nullable: true }
+}
+
+// The exported column schemas. These are a public interface for analysts'
+// notebooks: add new columns at the end, and never rename, retype or remove
+// existing ones.
+//
+// One row per game. end_time is a Unix timestamp in seconds; accuracies run
+// from 0 to 100 and are null until the game has engine evaluations. result is
+// written as in PGN: 1-0, 0-1, 1/2-1/2 or * when unknown.
+const GAME_COLUMNS: &[Column] = &[
+  column("id", Kind::Text),
+  column("source", Kind::Text),
+  column("source_id", Kind::Text),
+  column("end_time", Kind::Int),
+  column("time_control", Kind::Text),
+  column("white_player_id", Kind::Text),
+  column("white_player_name", Kind::Text),
+  column("white_player_rating", Kind::Int),
+  column("black_player_id", Kind::Text),
+  column("black_player_name", Kind::Text),
+  column("black_player_rating", Kind::Int),
+  nullable("white_accuracy", Kind::Float),
+  nullable("black_accuracy", Kind::Float),
+  column("eco", Kind::Text),
+  column("opening", Kind::Text),
+  column("variant", Kind::Text),
+  nullable("starting_fen", Kind::Text),
+  column("result", Kind::Text),
+  column("termination", Kind::Text),
+];
+
+// One row per ply. move_num counts plies from 0. Pieces are named by their
+// starting identity (e.g. "knight b"); captured_piece is empty for quiet
+// moves. Evals are in centipawns or moves-to-mate from white's point of view.
+const MOVE_COLUMNS: &[Column] = &[
+  column("game_id", Kind::Text),
+  column("move_num", Kind::Int),
+  column("color", Kind::Text),
+  column("moved_piece", Kind::Text),
+  column("starting_location", Kind::Text),
+  column("ending_location", Kind::Text),
+  column("captured_piece", Kind::Text),
+  column("capture_score", Kind::Int),
+  column("promotion_value", Kind::Int),
+  column("checkmate", Kind::Bool),
+  column("san", Kind::Text),
+  column("uci", Kind::Text),
+  nullable("fen", Kind::Text),
+  nullable("clock_ms", Kind::Int),
+  nullable("elapsed_ms", Kind::Int),
+  nullable("eval_cp", Kind::Int),
+  nullable("eval_mate", Kind::Int),
+  nullable("cp_loss", Kind::Int),
+  nullable("classification", Kind::Text),
+];
+
+// One row per piece per game. squares_visited is space-separated, starting
+// with the spawn square; captured_ply is null for pieces that survived.
+const PIECE_COLUMNS: &[Column] = &[
+  column("game_id", Kind::Text),
+  column("color", Kind::Text),
+  column("piece", Kind::Text),
+  column("spawn_square", Kind::Text),
+  column("squares_visited", Kind::Text),
+  column("move_count", Kind::Int),
+  nullable("captured_ply", Kind::Int),
+  column("captured_by", Kind::Text),
+];
+
+// One row per piece per player account, over the games matching the filter.
+// Counts are totals across those games; points use flat scoring.
+// average_survival_ply and promotion_rate are per game played.
+const PIECE_STATS_COLUMNS: &[Column] = &[
+  column("source", Kind::Text),
+  column("player_id", Kind::Text),
+  column("piece", Kind::Text),
+  column("games", Kind::Int),
+  column("captures", Kind::Int),
+  column("points", Kind::Int),
+  column("times_captured", Kind::Int),
+  column("survival_plies", Kind::Int),
+  column("promotions", Kind::Int),
+  column("checkmates", Kind::Int),
+  column("wins", Kind::Int),
+  column("average_survival_ply", Kind::Float),
+  column("promotion_rate", Kind::Float),
+];
+
+impl Table {
+  fn columns(self) -> &'static [Column] {
+    match self {
+      Table::Games => GAME_COLUMNS,
+      Table::Moves => MOVE_COLUMNS,
+      Table::Pieces => PIECE_COLUMNS,
+      Table::PieceStats => PIECE_STATS_COLUMNS,
+    }
+  }
+
+  // Derived tables aren't read straight from the database.
+  fn select(self) -> &'static str {
+    match self {
+      Table::PieceStats => unreachable!("piece stats are derived"),
+      Table::Games => "SELECT g.* FROM Games g WHERE 1 = 1",
+      Table::Moves => {
+        "SELECT m.* FROM Moves m JOIN Games g ON m.game_id = g.id WHERE 1 = 1"
+      }
+      Table::Pieces => {
+        "SELECT p.* FROM PieceLifecycles p JOIN Games g ON p.game_id = g.id
+          WHERE 1 = 1"
+      }
+    }
+  }
+
+  fn order_by(self) -> &'static str {
+    match self {
+      Table::PieceStats => unreachable!("piece stats are derived"),
+      Table::Games => " ORDER BY g.end_time, g.id",
+      Table::Moves => " ORDER BY g.end_time, m.game_id, m.move_num",
+      Table::Pieces => " ORDER BY g.end_time, p.game_id, p.color, p.piece",
+    }
+  }
+}
+
+enum Value {
+  Text(Option<String>),
+  Int(Option<i64>),
+  Float(Option<f64>),
+  Bool(Option<bool>),
+}
+
+impl Value {
+  fn to_json(&self) -> serde_json::Value {
+    match self {
+      Value::Text(v) => serde_json::json!(v),
+      Value::Int(v) => serde_json::json!(v),
+      Value::Float(v) => serde_json::json!(v),
+      Value::Bool(v) => serde_json::json!(v),
+    }
+  }
+
+  // Nulls become empty fields.
+  fn to_csv(&self) -> String {
+    match self {
+      Value::Text(v) => v.clone().unwrap_or_default(),
+      Value::Int(v) => v.map(|v| v.to_string()).unwrap_or_default(),
+      Value::Float(v) => v.map(|v| v.to_string()).unwrap_or_default(),
+      Value::Bool(v) => v.map(|v| v.to_string()).unwrap_or_default(),
+    }
+  }
+}
+
+// Reads a row's columns according to the export schema. Integer columns may
+// be stored narrower than 64 bits, so both widths are tried.
+fn row_values(row: &AnyRow, columns: &[Column]) -> sqlx::Result<Vec<Value>> {
+  columns
+    .iter()
+    .map(|c| {
+      Ok(match c.kind {
+        Kind::Text => Value::Text(row.try_get(c.name)?),
+        Kind::Int => Value::Int(match row.try_get::<Option<i64>, _>(c.name) {
+          Ok(v) => v,
+          Err(_) => row.try_get::<Option<i32>, _>(c.name)?.map(i64::from),
+        }),
+        Kind::Float => Value::Float(row.try_get(c.name)?),
+        Kind::Bool => Value::Bool(row.try_get(c.name)?),
+      })
+    })
+    .collect()
+}
+
+trait RowWriter {
+  fn write(&mut self, row: Vec<Value>) -> Result<()>;
+  fn finish(self: Box<Self>) -> Result<()>;
+}
+
+struct CsvWriter<W: Write> {
+  writer: csv::Writer<W>,
+}
+
+impl<W: Write> RowWriter for CsvWriter<W> {
+  fn write(&mut self, row: Vec<Value>) -> Result<()> {
+    self.writer.write_record(row.iter().map(Value::to_csv))?;
+    Ok(())
+  }
+
+  fn finish(mut self: Box<Self>) -> Result<()> {
+    self.writer.flush()?;
+    Ok(())
+  }
+}
+
+struct NdjsonWriter<W: Write> {
+  writer: W,
+  columns: &'static [Column],
+}
+
+impl<W: Write> RowWriter for NdjsonWriter<W> {
+  fn write(&mut self, row: Vec<Value>) -> Result<()> {
+    let object: serde_json::Map<String, serde_json::Value> = self
+      .columns
+      .iter()
+      .zip(row.iter())
+      .map(|(c, v)| (c.name.to_owned(), v.to_json()))
+      .collect();
+    serde_json::to_writer(&mut self.writer, &object)?;
+    self.writer.write_all(b"\n")?;
+    Ok(())
+  }
+
+  fn finish(mut self: Box<Self>) -> Result<()> {
+    self.writer.flush()?;
+    Ok(())
+  }
+}
+
+struct ParquetWriter<W: Write + Send> {
+  writer: parquet::arrow::ArrowWriter<W>,
+  schema: Arc<Schema>,
+  columns: &'static [Column],
+  rows: Vec<Vec<Value>>,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+  fn new(out: W, columns: &'static [Column]) -> Result<ParquetWriter<W>> {
+    let schema = Arc::new(Schema::new(
+      columns
+        .iter()
+        .map(|c| {
+          let data_type = match c.kind {
+            Kind::Text => DataType::Utf8,
+            Kind::Int => DataType::Int64,
+            Kind::Float => DataType::Float64,
+            Kind::Bool => DataType::Boolean,
+          };
+          Field::new(c.name, data_type, c.nullable)
+        })
+        .collect::<Vec<_>>(),
+    ));
+    let writer =
+      parquet::arrow::ArrowWriter::try_new(out, schema.clone(), None)?;
+    Ok(ParquetWriter { writer, schema, columns, rows: Vec::new() })
+  }
+
+  fn flush_batch(&mut self) -> Result<()> {
+    if self.rows.is_empty() {
+      return Ok(());
+    }
+    let rows = std::mem::take(&mut self.rows);
+    let arrays: Vec<ArrayRef> = (0..self.columns.len())
+      .map(|i| -> ArrayRef {
+        match self.columns[i].kind {
+          Kind::Text => Arc::new(StringArray::from(
+            rows
+              .iter()
+              .map(|r| match &r[i] {
+                Value::Text(v) => v.clone(),
+                _ => None,
+              })
+              .collect::<Vec<_>>(),
+          )),
+          Kind::Int => Arc::new(Int64Array::from(
+            rows
+              .iter()
+              .map(|r| match &r[i] {
+                Value::Int(v) => *v,
+                _ => None,
+              })
+              .collect::<Vec<_>>(),
+          )),
+          Kind::Float => Arc::new(Float64Array::from(
+            rows
+              .iter()
+              .map(|r| match &r[i] {
+                Value::Float(v) => *v,
+                _ => None,
+              })
+              .collect::<Vec<_>>(),
+          )),
+          Kind::Bool => Arc::new(BooleanArray::from(
+            rows
+              .iter()
+              .map(|r| match &r[i] {
+                Value::Bool(v) => *v,
+                _ => None,
+              })
+              .collect::<Vec<_>>(),
+          )),
+        }
+      })
+      .collect();
+    let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+    self.writer.write(&batch)?;
+    Ok(())
+  }
+}
+
+impl<W: Write + Send> RowWriter for ParquetWriter<W> {
+  fn write(&mut self, row: Vec<Value>) -> Result<()> {
+    self.rows.push(row);
+    if self.rows.len() >= PARQUET_BATCH_SIZE {
+      self.flush_batch()?;
+    }
+    Ok(())
+  }
+
+  fn finish(mut self: Box<Self>) -> Result<()> {
+    self.flush_batch()?;
+    self.writer.close()?;
+    Ok(())
+  }
+}
+
+// Streams every row of `table` belonging to games that match the filter to
+// `out`, returning the number of rows written, or of games for PGN.
+pub async fn export<W: Write + Send + 'static>(
+  db: &sqlx::Pool<sqlx::Any>,
+  table: Table,
+  format: Format,
+  filter: &db::GameFilter,
+  out: W,
+) -> Result<usize> {
+  if format == Format::Pgn {
+    return export_pgn(db, filter, out).await;
+  }
+  let columns = table.columns();
+  let mut writer: Box<dyn RowWriter> = match format {
+    Format::Csv => {
+      let mut writer = csv::Writer::from_writer(out);
+      writer.write_record(columns.iter().map(|c| c.name))?;
+      Box::new(CsvWriter { writer })
+    }
+    Format::Ndjson => Box::new(NdjsonWriter { writer: out, columns }),
+    Format::Parquet => Box::new(ParquetWriter::new(out, columns)?),
+    Format::Pgn => unreachable!("PGN is exported a game at a time"),
+  };
+  if table == Table::PieceStats {
+    let count = export_piece_stats(db, filter, writer.as_mut()).await?;
+    writer.finish()?;
+    return Ok(count);
+  }
+
+  let mut sql = String::from(table.select());
+  let values = filter.push_conditions(&mut sql);
+  sql.push_str(table.order_by());
+  let sql = Dialect::of(db).placeholders(&sql);
+
+  let mut count = 0;
+  let mut rows = db::bind_all(sqlx::query(&sql), values).fetch(db);
+  while let Some(row) = rows.try_next().await? {
+    writer.write(row_values(&row, columns)?)?;
+    count += 1;
+  }
+  writer.finish()?;
+  Ok(count)
+}
+
+// Writes piece stats for the filter's player, or for every player from the
+// filter's source if it doesn't name one, returning the number of rows
+// written.
+async fn export_piece_stats(
+  db: &sqlx::Pool<sqlx::Any>,
+  filter: &db::GameFilter,
+  writer: &mut dyn RowWriter,
+) -> Result<usize> {
+  let mut players = Vec::new();
+  match &filter.player_id {
+    Some(player_id) => players.push((filter.source.clone(), player_id.clone())),
+    None => loop {
+      let page =
+        db::players(db, PLAYER_PAGE_SIZE, players.len() as i64).await?;
+      let done = (page.len() as i64) < PLAYER_PAGE_SIZE;
+      players.extend(page.into_iter().map(|p| (Some(p.source), p.id)));
+      if done {
+        break;
+      }
+    },
+  }
+
+  let mut count = 0;
+  for (source, player_id) in players {
+    if filter.source.is_some() && source != filter.source {
+      continue;
+    }
+    let stats_filter = stats::Filter {
+      source: source.clone(),
+      since: filter.since,
+      until: filter.until,
+      time_control: filter.time_control.clone(),
+      eco: filter.eco.clone(),
+      ..Default::default()
+    };
+    for p in stats::piece_stats(db, &player_id, &stats_filter).await? {
+      if p.games == 0 {
+        continue;
+      }
+      writer.write(vec![
+        Value::Text(Some(source.clone().unwrap_or_default())),
+        Value::Text(Some(player_id.clone())),
+        Value::Text(Some(p.piece.clone())),
+        Value::Int(Some(p.games)),
+        Value::Int(Some(p.captures)),
+        Value::Int(Some(p.points)),
+        Value::Int(Some(p.times_captured)),
+        Value::Int(Some(p.survival_plies)),
+        Value::Int(Some(p.promotions)),
+        Value::Int(Some(p.checkmates)),
+        Value::Int(Some(p.wins)),
+        Value::Float(Some(p.average_survival_ply())),
+        Value::Float(Some(p.promotion_rate())),
+      ])?;
+      count += 1;
+    }
+  }
+  Ok(count)
+}
+
+// Writes every game that matches the filter as PGN, returning the number of
+// games written.
+async fn export_pgn<W: Write>(
+  db: &sqlx::Pool<sqlx::Any>,
+  filter: &db::GameFilter,
+  mut out: W,
+) -> Result<usize> {
+  let mut sql = String::from(Table::Games.select());
+  let values = filter.push_conditions(&mut sql);
+  sql.push_str(Table::Games.order_by());
+  let sql = Dialect::of(db).placeholders(&sql);
+
+  // Games are read up front rather than streamed, so that reading each game's
+  // moves doesn't need a second connection.
+  let rows = db::bind_all(sqlx::query(&sql), values).fetch_all(db).await?;
+  for row in rows.iter() {
+    let game = db::Game::from_row(row)?;
+    let moves = db::moves_for_game(db, &game.id).await?;
+    write_pgn(&mut out, &game, &moves)?;
+  }
+  out.flush()?;
+  Ok(rows.len())
+}
+
+fn write_pgn<W: Write>(
+  out: &mut W,
+  game: &db::Game,
+  moves: &[db::Move],
+) -> Result<()> {
+  let end_time = chrono::NaiveDateTime::from_timestamp_opt(game.end_time, 0)
+    .ok_or_else(|| Error::InvalidEndTime {
+      game_id: game.id.clone(),
+      end_time: game.end_time,
+    })?;
+  let date = end_time.format("%Y.%m.%d").to_string();
+  let mut headers = vec![
+    ("Event", String::from("?")),
+    ("Site", site(game)),
+    ("Date", date.clone()),
+    ("White", game.white_player_name.clone()),
+    ("Black", game.black_player_name.clone()),
+    ("Result", game.result.clone()),
+    ("UTCDate", date),
+    ("UTCTime", end_time.format("%H:%M:%S").to_string()),
+    ("WhiteElo", game.white_player_rating.to_string()),
+    ("BlackElo", game.black_player_rating.to_string()),
+    ("TimeControl", game.time_control.clone()),
+  ];
+  // Odds games are marked the way lichess marks games set up from a
+  // position, which is how they're recognized when read back in.
+  if game.variant == db::ODDS_VARIANT {
+    headers.push(("Variant", String::from("From Position")));
+  } else if game.variant != db::STANDARD_VARIANT {
+    headers.push(("Variant", game.variant.clone()));
+  }
+  if let Some(fen) = &game.starting_fen {
+    headers.push(("SetUp", String::from("1")));
+    headers.push(("FEN", fen.clone()));
+  }
+  if !game.eco.is_empty() {
+    headers.push(("ECO", game.eco.clone()));
+  }
+  if !game.opening.is_empty() {
+    headers.push(("Opening", game.opening.clone()));
+  }
+  if !game.termination.is_empty() {
+    headers.push(("Termination", game.termination.clone()));
+  }
+  if let Some(link) = link(game) {
+    headers.push(("Link", link));
+  }
+  for (name, value) in headers {
+    writeln!(
+      out,
+      "[{} \"{}\"]",
+      name,
+      value.replace('\\', "\\\\").replace('"', "\\\"")
+    )?;
+  }
+  writeln!(out)?;
+
+  let mut tokens = Vec::new();
+  let mut board = match &game.starting_fen {
+    Some(fen) => dumbchess::Board::from_fen(fen).map_err(|_| {
+      Error::InvalidFen { game_id: game.id.clone(), fen: fen.clone() }
+    })?,
+    None => dumbchess::Board::starting(),
+  };
+  for (i, m) in moves.iter().enumerate() {
+    let illegal =
+      || Error::IllegalMove { game_id: game.id.clone(), uci: m.uci_move() };
+    let (start, end, promotion) = uci_squares(m).ok_or_else(illegal)?;
+    // Writing SAN checks the move is legal even when the source supplied it.
+    let san = board.san(start, end, promotion).map_err(|_| illegal())?;
+    // Games set up with black to move start on an odd ply.
+    if m.move_num % 2 == 0 {
+      tokens.push(format!("{}.", m.move_num / 2 + 1));
+    } else if i == 0 {
+      tokens.push(format!("{}...", m.move_num / 2 + 1));
+    }
+    tokens.push(if m.san.is_empty() { san } else { m.san.clone() });
+    if let Some(comment) = comment(m) {
+      tokens.push(comment);
+    }
+    board.make_move(&start, &end, promotion).map_err(|_| illegal())?;
+  }
+  tokens.push(game.result.clone());
+
+  let mut line_len = 0;
+  for token in tokens {
+    if line_len > 0 && line_len + 1 + token.len() > PGN_LINE_WIDTH {
+      writeln!(out)?;
+      line_len = 0;
+    } else if line_len > 0 {
+      write!(out, " ")?;
+      line_len += 1;
+    }
+    write!(out, "{}", token)?;
+    line_len += token.len();
+  }
+  writeln!(out)?;
+  writeln!(out)?;
+  Ok(())
+}
+
+fn site(game: &db::Game) -> String {
+  match game.source.as_str() {
+    "lichess.org" => format!("https://lichess.org/{}", game.source_id),
+    "chess.com" => String::from("Chess.com"),
+    source => source.to_owned(),
+  }
+}
+
+fn link(game: &db::Game) -> Option<String> {
+  match game.source.as_str() {
+    "chess.com" if !game.source_id.is_empty() => {
+      Some(format!("https://www.chess.com/game/live/{}", game.source_id))
+    }
+    _ => None,
+  }
+}
+
+// The move's clock and evaluation as a comment in the same form lichess and
+// chess.com use, so they survive being read back in.
+fn comment(m: &db::Move) -> Option<String> {
+  let mut commands = Vec::new();
+  if let Some(eval) = m.eval() {
+    commands.push(match eval {
+      Eval::Centipawns(cp) => format!("[%eval {:.2}]", cp as f64 / 100.0),
+      Eval::Mate(n) => format!("[%eval #{}]", n),
+    });
+  }
+  if let Some(clock) = m.clock_ms {
+    commands.push(format!("[%clk {}]", format_clock(clock)));
+  }
+  if let Some(elapsed) = m.elapsed_ms {
+    commands.push(format!("[%emt {}]", format_clock(elapsed)));
+  }
+  if commands.is_empty() {
+    None
+  } else {
+    Some(format!("{{ {} }}", commands.join(" ")))
+  }
+}
+
+// Formats milliseconds as H:MM:SS, with tenths of a second when there are any.
+fn format_clock(ms: i64) -> String {
+  let (seconds, tenths) = (ms / 1000, ms % 1000 / 100);
+  let hms =
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+  if tenths == 0 {
+    hms
+  } else {
+    format!("{}.{}", hms, tenths)
+  }
+}
+
+// The squares and promotion of a move, from its UCI form.
+fn uci_squares(m: &db::Move) -> Option<(Square, Square, Option<Role>)> {
+  let uci = m.uci_move();
+  let start = Square::parse(uci.get(0..2)?.as_bytes())?;
+  let end = Square::parse(uci.get(2..4)?.as_bytes())?;
+  let promotion = match uci.chars().nth(4) {
+    Some(letter) => Some(Role::from_letter(letter)?),
+    None => None,
+  };
+  Some((start, end, promotion))
+}
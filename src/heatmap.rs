@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use sqlx::Row;
+
+use crate::db::{self, Dialect};
+
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+  pub source: Option<String>,
+  pub player_id: Option<String>,
+  pub piece: Option<String>,
+  pub color: Option<String>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Kind {
+  // Times a piece stood on each square: its starting square once per game,
+  // then each square it moved to. Pieces that never move still count on the
+  // squares they start on.
+  Occupancy,
+  // Squares on which the piece captured something
+  Captures,
+}
+
+// Per-square counts, indexed by rank * 8 + file starting from a1.
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+  pub occupancy: [u32; 64],
+  pub captures: [u32; 64],
+}
+
+impl Heatmap {
+  pub fn get(&self, kind: Kind) -> &[u32; 64] {
+    match kind {
+      Kind::Occupancy => &self.occupancy,
+      Kind::Captures => &self.captures,
+    }
+  }
+
+  // Renders the board from white's point of view, rank 8 at the top.
+  pub fn to_text(&self, kind: Kind) -> String {
+    let counts = self.get(kind);
+    let width = counts.iter().max().unwrap_or(&0).to_string().len().max(1);
+    let mut out = String::new();
+    for rank in (0..8).rev() {
+      write!(out, "{} ", rank + 1).unwrap();
+      for file in 0..8 {
+        write!(out, " {:>width$}", counts[rank * 8 + file], width = width)
+          .unwrap();
+      }
+      out.push('\n');
+    }
+    out.push_str("  ");
+    for file in FILES.chars() {
+      write!(out, " {:>width$}", file, width = width).unwrap();
+    }
+    out.push('\n');
+    out
+  }
+
+  pub fn to_json(&self) -> String {
+    let by_square = |counts: &[u32; 64]| -> BTreeMap<String, u32> {
+      (0..64).map(|i| (square_name(i), counts[i])).collect()
+    };
+    let mut maps = BTreeMap::new();
+    maps.insert("occupancy", by_square(&self.occupancy));
+    maps.insert("captures", by_square(&self.captures));
+    serde_json::to_string_pretty(&maps).unwrap()
+  }
+
+  // Renders an 8x8 board where each square is shaded in proportion to its
+  // count relative to the busiest square.
+  pub fn to_svg(&self, kind: Kind) -> String {
+    const SIZE: usize = 60;
+    let counts = self.get(kind);
+    let max = *counts.iter().max().unwrap_or(&0) as f64;
+    let mut out = format!(
+      "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" \
+       viewBox=\"0 0 {0} {0}\">\n",
+      SIZE * 8
+    );
+    for rank in 0..8 {
+      for file in 0..8 {
+        let count = counts[rank * 8 + file];
+        let x = file * SIZE;
+        let y = (7 - rank) * SIZE;
+        let base = if (rank + file) % 2 == 0 { "#b58863" } else { "#f0d9b5" };
+        let heat = if max > 0.0 { count as f64 / max } else { 0.0 };
+        writeln!(
+          out,
+          "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+          x, y, SIZE, SIZE, base
+        )
+        .unwrap();
+        writeln!(
+          out,
+          "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+           fill=\"#d7301f\" fill-opacity=\"{:.3}\"><title>{}: {}</title></rect>",
+          x,
+          y,
+          SIZE,
+          SIZE,
+          heat * 0.85,
+          square_name(rank * 8 + file),
+          count
+        )
+        .unwrap();
+        if count > 0 {
+          writeln!(
+            out,
+            "  <text x=\"{}\" y=\"{}\" font-family=\"sans-serif\" \
+             font-size=\"14\" text-anchor=\"middle\">{}</text>",
+            x + SIZE / 2,
+            y + SIZE / 2 + 5,
+            count
+          )
+          .unwrap();
+        }
+      }
+    }
+    out.push_str("</svg>\n");
+    out
+  }
+}
+
+const FILES: &str = "abcdefgh";
+
+fn square_name(index: usize) -> String {
+  format!("{}{}", &FILES[index % 8..index % 8 + 1], index / 8 + 1)
+}
+
+fn square_index(square: &str) -> Option<usize> {
+  let bytes = square.as_bytes();
+  if bytes.len() != 2 {
+    return None;
+  }
+  let file = bytes[0].checked_sub(b'a').filter(|f| *f < 8)?;
+  let rank = bytes[1].checked_sub(b'1').filter(|r| *r < 8)?;
+  Some(rank as usize * 8 + file as usize)
+}
+
+impl Filter {
+  // Appends the filter's conditions to a query joining Games `g` to a table
+  // of pieces by color, e.g. Moves or PieceLifecycles, along with the values
+  // to bind for them in order.
+  fn push_conditions(
+    &self,
+    sql: &mut String,
+    table: &str,
+    piece_column: &str,
+  ) -> Vec<db::Value> {
+    let mut values = db::GameFilter {
+      source: self.source.clone(),
+      player_id: self.player_id.clone(),
+      ..Default::default()
+    }
+    .push_conditions(sql);
+    // Only the player's own pieces, not their opponents'.
+    if let Some(player_id) = &self.player_id {
+      write!(
+        sql,
+        " AND (({0}.color = 'white' AND g.white_player_id = ?)
+          OR ({0}.color = 'black' AND g.black_player_id = ?))",
+        table
+      )
+      .unwrap();
+      values.push(db::Value::Text(player_id.clone()));
+      values.push(db::Value::Text(player_id.clone()));
+    }
+    if let Some(piece) = &self.piece {
+      write!(sql, " AND {}.{} = ?", table, piece_column).unwrap();
+      values.push(db::Value::Text(piece.clone()));
+    }
+    if let Some(color) = &self.color {
+      write!(sql, " AND {}.color = ?", table).unwrap();
+      values.push(db::Value::Text(color.clone()));
+    }
+    values
+  }
+}
+
+// Aggregates where the pieces matching the filter stood, from their
+// lifecycles, and where they captured, from their moves.
+pub async fn heatmap(
+  db: &sqlx::Pool<sqlx::Any>,
+  filter: &Filter,
+) -> sqlx::Result<Heatmap> {
+  let mut heatmap = Heatmap { occupancy: [0; 64], captures: [0; 64] };
+
+  let mut sql = String::from(
+    "SELECT l.squares_visited
+      FROM PieceLifecycles l JOIN Games g ON l.game_id = g.id WHERE 1 = 1",
+  );
+  let values = filter.push_conditions(&mut sql, "l", "piece");
+  let sql = Dialect::of(db).placeholders(&sql);
+  for row in db::bind_all(sqlx::query(&sql), values).fetch_all(db).await? {
+    let squares: String = row.try_get("squares_visited")?;
+    for i in squares.split_whitespace().filter_map(square_index) {
+      heatmap.occupancy[i] += 1;
+    }
+  }
+
+  let mut sql = String::from(
+    "SELECT m.ending_location
+      FROM Moves m JOIN Games g ON m.game_id = g.id
+      WHERE m.captured_piece <> ''",
+  );
+  let values = filter.push_conditions(&mut sql, "m", "moved_piece");
+  let sql = Dialect::of(db).placeholders(&sql);
+  for row in db::bind_all(sqlx::query(&sql), values).fetch_all(db).await? {
+    let square: String = row.try_get("ending_location")?;
+    if let Some(i) = square_index(&square) {
+      heatmap.captures[i] += 1;
+    }
+  }
+  Ok(heatmap)
+}
@@ -0,0 +1,1168 @@
+use std::cmp::Reverse;
+
+use itertools::Itertools;
+use sqlx::Row;
+use thiserror::Error as ThisError;
+
+use crate::db::{self, Dialect, OnConflict};
+use crate::stats::Scoring;
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+  #[error("unknown team: {0}")]
+  UnknownTeam(String),
+  #[error("{piece} is not held by team {team_id}")]
+  PieceNotHeld { team_id: String, piece: PieceId },
+  #[error("{0} is already on a roster")]
+  PieceAlreadyRostered(PieceId),
+  #[error("{piece} changes hands after {time}")]
+  PieceMovesLater { piece: PieceId, time: i64 },
+  #[error("team {team_id} bid {bid} with only {budget} left")]
+  BidOverBudget { team_id: String, bid: i32, budget: i32 },
+  #[error("trade {id} is {status}, expected {expected}")]
+  InvalidTradeState { id: String, status: TradeStatus, expected: TradeStatus },
+  #[error("unrecognized {0}: {1}")]
+  UnrecognizedValue(&'static str, String),
+  #[error("database error")]
+  Database {
+    #[from]
+    source: sqlx::Error,
+  },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// A rosterable piece: one piece identity (e.g. "knight b") belonging to one
+// player account, regardless of which color the account plays.
+#[derive(
+  Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Clone, serde::Serialize,
+)]
+pub struct PieceId {
+  pub source: String,
+  pub player_id: String,
+  pub piece: String,
+}
+
+// PieceId source for pieces rostered by person rather than by account. The
+// player_id is then a person ID, and the piece scores across every account
+// linked to that person.
+pub const PERSON_SOURCE: &str = "person";
+
+impl PieceId {
+  pub fn person(person_id: &str, piece: &str) -> PieceId {
+    PieceId {
+      source: PERSON_SOURCE.to_owned(),
+      player_id: person_id.to_owned(),
+      piece: piece.to_owned(),
+    }
+  }
+}
+
+impl std::fmt::Display for PieceId {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}/{} {}", self.source, self.player_id, self.piece)
+  }
+}
+
+// Parses the Display form, e.g. "lichess/alice knight g".
+impl std::str::FromStr for PieceId {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let (source, rest) = s
+      .split_once('/')
+      .ok_or_else(|| Error::UnrecognizedValue("piece", s.to_owned()))?;
+    let (player_id, piece) = rest
+      .split_once(' ')
+      .ok_or_else(|| Error::UnrecognizedValue("piece", s.to_owned()))?;
+    Ok(PieceId {
+      source: source.to_owned(),
+      player_id: player_id.to_owned(),
+      piece: piece.trim().to_owned(),
+    })
+  }
+}
+
+#[derive(
+  Debug, Eq, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum WaiverMode {
+  // Claims go to the team with the best (lowest) waiver priority; a team that
+  // wins a claim drops to the back of the order.
+  Priority,
+  // Claims go to the highest blind bid; waiver priority breaks ties.
+  Faab,
+}
+
+impl std::fmt::Display for WaiverMode {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        WaiverMode::Priority => "priority",
+        WaiverMode::Faab => "faab",
+      }
+    )
+  }
+}
+
+impl std::str::FromStr for WaiverMode {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "priority" => Ok(WaiverMode::Priority),
+      "faab" => Ok(WaiverMode::Faab),
+      s => Err(Error::UnrecognizedValue("waiver mode", s.to_owned())),
+    }
+  }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+  FreeAgentAdd,
+  WaiverClaim,
+  TradeIn,
+  TradeOut,
+  Drop,
+}
+
+impl TransactionKind {
+  pub fn acquires(&self) -> bool {
+    match self {
+      TransactionKind::FreeAgentAdd => true,
+      TransactionKind::WaiverClaim => true,
+      TransactionKind::TradeIn => true,
+      TransactionKind::TradeOut => false,
+      TransactionKind::Drop => false,
+    }
+  }
+}
+
+impl std::fmt::Display for TransactionKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        TransactionKind::FreeAgentAdd => "free_agent_add",
+        TransactionKind::WaiverClaim => "waiver_claim",
+        TransactionKind::TradeIn => "trade_in",
+        TransactionKind::TradeOut => "trade_out",
+        TransactionKind::Drop => "drop",
+      }
+    )
+  }
+}
+
+impl std::str::FromStr for TransactionKind {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "free_agent_add" => Ok(TransactionKind::FreeAgentAdd),
+      "waiver_claim" => Ok(TransactionKind::WaiverClaim),
+      "trade_in" => Ok(TransactionKind::TradeIn),
+      "trade_out" => Ok(TransactionKind::TradeOut),
+      "drop" => Ok(TransactionKind::Drop),
+      s => Err(Error::UnrecognizedValue("transaction kind", s.to_owned())),
+    }
+  }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStatus {
+  Proposed,
+  Accepted,
+  Rejected,
+  Vetoed,
+  Executed,
+}
+
+impl std::fmt::Display for TradeStatus {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        TradeStatus::Proposed => "proposed",
+        TradeStatus::Accepted => "accepted",
+        TradeStatus::Rejected => "rejected",
+        TradeStatus::Vetoed => "vetoed",
+        TradeStatus::Executed => "executed",
+      }
+    )
+  }
+}
+
+impl std::str::FromStr for TradeStatus {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "proposed" => Ok(TradeStatus::Proposed),
+      "accepted" => Ok(TradeStatus::Accepted),
+      "rejected" => Ok(TradeStatus::Rejected),
+      "vetoed" => Ok(TradeStatus::Vetoed),
+      "executed" => Ok(TradeStatus::Executed),
+      s => Err(Error::UnrecognizedValue("trade status", s.to_owned())),
+    }
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Team {
+  pub id: String,
+  pub league_id: String,
+  pub name: String,
+  pub waiver_priority: i32,
+  pub faab_budget: i32,
+}
+
+// A single change to a team's roster. Rosters are never stored directly; they
+// are always derived by replaying these in (effective_time, seq) order.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RosterTransaction {
+  pub id: String,
+  pub league_id: String,
+  pub team_id: String,
+  pub piece: PieceId,
+  pub kind: TransactionKind,
+  pub effective_time: i64,
+  // Position in the league's transaction log, breaking ties between
+  // transactions with the same effective_time.
+  pub seq: i64,
+}
+
+// The span of time during which a team held a piece. Only games whose
+// end_time falls inside [start, end) count towards the team's score.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HoldingWindow {
+  pub piece: PieceId,
+  pub start: i64,
+  pub end: Option<i64>,
+}
+
+impl HoldingWindow {
+  pub fn contains(&self, time: i64) -> bool {
+    self.start <= time && self.end.map(|end| time < end).unwrap_or(true)
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Trade {
+  pub id: String,
+  pub league_id: String,
+  pub proposer_team_id: String,
+  pub recipient_team_id: String,
+  pub offered: Vec<PieceId>,
+  pub requested: Vec<PieceId>,
+  pub status: TradeStatus,
+  pub proposed_time: i64,
+}
+
+// A claim waiting for the next waiver run, which awards each claimed piece to
+// at most one team.
+#[derive(Debug, Clone)]
+pub struct WaiverClaim {
+  pub id: String,
+  pub league_id: String,
+  pub team_id: String,
+  pub add: PieceId,
+  pub drop: Option<PieceId>,
+  pub bid: i32,
+  pub submitted_time: i64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct League {
+  pub id: String,
+  pub name: String,
+  pub waiver_mode: WaiverMode,
+  // Points each piece that played a game scores for its holder when its side
+  // wins, on top of its captures.
+  pub win_points: i32,
+  // How captures made by rostered pieces score
+  pub scoring: Scoring,
+  pub teams: Vec<Team>,
+  // Kept sorted by (effective_time, seq), including transactions recorded
+  // with an effective_time in the past.
+  pub transactions: Vec<RosterTransaction>,
+  pub trades: Vec<Trade>,
+}
+
+impl League {
+  pub fn new(
+    name: &str,
+    waiver_mode: WaiverMode,
+    win_points: i32,
+    scoring: Scoring,
+  ) -> League {
+    League {
+      id: uuid::Uuid::new_v4().to_string(),
+      name: name.to_owned(),
+      waiver_mode,
+      win_points,
+      scoring,
+      teams: Vec::new(),
+      transactions: Vec::new(),
+      trades: Vec::new(),
+    }
+  }
+
+  pub fn add_team(&mut self, name: &str, faab_budget: i32) -> &Team {
+    let team = Team {
+      id: uuid::Uuid::new_v4().to_string(),
+      league_id: self.id.clone(),
+      name: name.to_owned(),
+      waiver_priority: self.teams.len() as i32 + 1,
+      faab_budget,
+    };
+    self.teams.push(team);
+    self.teams.last().unwrap()
+  }
+
+  pub fn team(&self, team_id: &str) -> Result<&Team> {
+    self
+      .teams
+      .iter()
+      .find(|t| t.id == team_id)
+      .ok_or_else(|| Error::UnknownTeam(team_id.to_owned()))
+  }
+
+  // Returns the team holding the piece at the given time, if any. Relies on
+  // transactions being sorted, so the last match is the latest.
+  pub fn holder(&self, piece: &PieceId, time: i64) -> Option<&str> {
+    self
+      .transactions
+      .iter()
+      .rev()
+      .find(|t| &t.piece == piece && t.effective_time <= time)
+      .filter(|t| t.kind.acquires())
+      .map(|t| t.team_id.as_str())
+  }
+
+  pub fn roster(&self, team_id: &str, time: i64) -> Vec<PieceId> {
+    self
+      .transactions
+      .iter()
+      .map(|t| &t.piece)
+      .unique()
+      .filter(|p| self.holder(p, time) == Some(team_id))
+      .cloned()
+      .collect()
+  }
+
+  pub fn holding_windows(&self, team_id: &str) -> Vec<HoldingWindow> {
+    let mut windows: Vec<HoldingWindow> = Vec::new();
+    for t in self.transactions.iter().filter(|t| t.team_id == team_id) {
+      if t.kind.acquires() {
+        windows.push(HoldingWindow {
+          piece: t.piece.clone(),
+          start: t.effective_time,
+          end: None,
+        });
+      } else if let Some(w) =
+        windows.iter_mut().rev().find(|w| w.piece == t.piece && w.end.is_none())
+      {
+        w.end = Some(t.effective_time);
+      }
+    }
+    windows
+  }
+
+  pub fn add_free_agent(
+    &mut self,
+    team_id: &str,
+    add: PieceId,
+    drop: Option<PieceId>,
+    time: i64,
+  ) -> Result<Vec<RosterTransaction>> {
+    self.team(team_id)?;
+    if self.holder(&add, time).is_some() {
+      return Err(Error::PieceAlreadyRostered(add));
+    }
+    if self.moves_after(&add, time) {
+      return Err(Error::PieceMovesLater { piece: add, time });
+    }
+    let mut recorded = Vec::new();
+    if let Some(drop) = drop {
+      recorded.push(self.drop_piece(team_id, drop, time)?);
+    }
+    recorded.push(self.record(
+      team_id,
+      add,
+      TransactionKind::FreeAgentAdd,
+      time,
+    ));
+    Ok(recorded)
+  }
+
+  pub fn drop_piece(
+    &mut self,
+    team_id: &str,
+    piece: PieceId,
+    time: i64,
+  ) -> Result<RosterTransaction> {
+    self.check_held(team_id, &piece, time)?;
+    Ok(self.record(team_id, piece, TransactionKind::Drop, time))
+  }
+
+  // Checks a waiver claim against the league as of the given time. Whether it
+  // wins is only decided when waivers are processed.
+  pub fn claim_waiver(
+    &self,
+    team_id: &str,
+    add: PieceId,
+    drop: Option<PieceId>,
+    bid: i32,
+    time: i64,
+  ) -> Result<WaiverClaim> {
+    let team = self.team(team_id)?;
+    if self.holder(&add, time).is_some() {
+      return Err(Error::PieceAlreadyRostered(add));
+    }
+    if self.moves_after(&add, time) {
+      return Err(Error::PieceMovesLater { piece: add, time });
+    }
+    if let Some(drop) = drop.as_ref() {
+      self.check_held(team_id, drop, time)?;
+    }
+    if self.waiver_mode == WaiverMode::Faab && bid > team.faab_budget {
+      return Err(Error::BidOverBudget {
+        team_id: team_id.to_owned(),
+        bid,
+        budget: team.faab_budget,
+      });
+    }
+    Ok(WaiverClaim {
+      id: uuid::Uuid::new_v4().to_string(),
+      league_id: self.id.clone(),
+      team_id: team_id.to_owned(),
+      add,
+      drop,
+      bid,
+      submitted_time: time,
+    })
+  }
+
+  pub fn propose_trade(
+    &mut self,
+    proposer_team_id: &str,
+    recipient_team_id: &str,
+    offered: Vec<PieceId>,
+    requested: Vec<PieceId>,
+    time: i64,
+  ) -> Result<Trade> {
+    for piece in offered.iter() {
+      self.check_held(proposer_team_id, piece, time)?;
+    }
+    for piece in requested.iter() {
+      self.check_held(recipient_team_id, piece, time)?;
+    }
+    let trade = Trade {
+      id: uuid::Uuid::new_v4().to_string(),
+      league_id: self.id.clone(),
+      proposer_team_id: proposer_team_id.to_owned(),
+      recipient_team_id: recipient_team_id.to_owned(),
+      offered,
+      requested,
+      status: TradeStatus::Proposed,
+      proposed_time: time,
+    };
+    self.trades.push(trade.clone());
+    Ok(trade)
+  }
+
+  // Swaps the pieces of an accepted trade, effective at the given time. Both
+  // sides must still hold everything in the trade.
+  pub fn execute_trade(
+    &mut self,
+    trade: &mut Trade,
+    time: i64,
+  ) -> Result<Vec<RosterTransaction>> {
+    trade.check_status(TradeStatus::Accepted)?;
+    for piece in trade.offered.iter() {
+      self.check_held(&trade.proposer_team_id, piece, time)?;
+    }
+    for piece in trade.requested.iter() {
+      self.check_held(&trade.recipient_team_id, piece, time)?;
+    }
+
+    let mut recorded = Vec::new();
+    for (from, to, pieces) in [
+      (&trade.proposer_team_id, &trade.recipient_team_id, &trade.offered),
+      (&trade.recipient_team_id, &trade.proposer_team_id, &trade.requested),
+    ]
+    .iter()
+    {
+      for piece in pieces.iter() {
+        recorded.push(self.record(
+          from,
+          piece.clone(),
+          TransactionKind::TradeOut,
+          time,
+        ));
+        recorded.push(self.record(
+          to,
+          piece.clone(),
+          TransactionKind::TradeIn,
+          time,
+        ));
+      }
+    }
+    trade.status = TradeStatus::Executed;
+    for t in self.trades.iter_mut().filter(|t| t.id == trade.id) {
+      t.status = TradeStatus::Executed;
+    }
+    Ok(recorded)
+  }
+
+  // Resolves a batch of waiver claims, one contested piece at a time in the
+  // order the pieces were first claimed. Claims that can no longer be
+  // fulfilled (the drop piece is gone, or the bid exceeds the remaining
+  // budget) are skipped, as are pieces that are rostered or change hands
+  // after the given time.
+  pub fn process_waivers(
+    &mut self,
+    mut claims: Vec<WaiverClaim>,
+    time: i64,
+  ) -> Result<Vec<RosterTransaction>> {
+    claims.sort_by_key(|c| c.submitted_time);
+    let pieces: Vec<PieceId> =
+      claims.iter().map(|c| c.add.clone()).unique().collect();
+
+    let mut recorded = Vec::new();
+    for piece in pieces {
+      if self.holder(&piece, time).is_some() || self.moves_after(&piece, time) {
+        continue;
+      }
+      let winner = claims
+        .iter()
+        .filter(|c| c.add == piece)
+        .filter_map(|c| self.team(&c.team_id).ok().map(|t| (c, t)))
+        .filter(|(c, t)| {
+          c.drop
+            .as_ref()
+            .map(|d| self.holder(d, time) == Some(t.id.as_str()))
+            .unwrap_or(true)
+        })
+        .filter(|(c, t)| {
+          self.waiver_mode == WaiverMode::Priority || c.bid <= t.faab_budget
+        })
+        .min_by_key(|(c, t)| match self.waiver_mode {
+          WaiverMode::Priority => (Reverse(0), t.waiver_priority),
+          WaiverMode::Faab => (Reverse(c.bid), t.waiver_priority),
+        })
+        .map(|(c, _)| c.clone());
+
+      if let Some(claim) = winner {
+        if let Some(drop) = claim.drop {
+          recorded.push(self.record(
+            &claim.team_id,
+            drop,
+            TransactionKind::Drop,
+            time,
+          ));
+        }
+        recorded.push(self.record(
+          &claim.team_id,
+          claim.add,
+          TransactionKind::WaiverClaim,
+          time,
+        ));
+        self.charge_waiver(&claim.team_id, claim.bid);
+      }
+    }
+    Ok(recorded)
+  }
+
+  fn charge_waiver(&mut self, team_id: &str, bid: i32) {
+    match self.waiver_mode {
+      WaiverMode::Priority => {
+        let num_teams = self.teams.len() as i32;
+        let claimed_priority = self.team(team_id).unwrap().waiver_priority;
+        for team in self.teams.iter_mut() {
+          if team.id == team_id {
+            team.waiver_priority = num_teams;
+          } else if team.waiver_priority > claimed_priority {
+            team.waiver_priority -= 1;
+          }
+        }
+      }
+      WaiverMode::Faab => {
+        for team in self.teams.iter_mut().filter(|t| t.id == team_id) {
+          team.faab_budget -= bid;
+        }
+      }
+    }
+  }
+
+  // Whether the piece has a transaction after the given time. Acquiring it at
+  // that time would open a holding window overlapping its later holders'.
+  fn moves_after(&self, piece: &PieceId, time: i64) -> bool {
+    self
+      .transactions
+      .iter()
+      .any(|t| &t.piece == piece && t.effective_time > time)
+  }
+
+  fn check_held(
+    &self,
+    team_id: &str,
+    piece: &PieceId,
+    time: i64,
+  ) -> Result<()> {
+    self.team(team_id)?;
+    if self.holder(piece, time) != Some(team_id) {
+      return Err(Error::PieceNotHeld {
+        team_id: team_id.to_owned(),
+        piece: piece.clone(),
+      });
+    }
+    Ok(())
+  }
+
+  fn record(
+    &mut self,
+    team_id: &str,
+    piece: PieceId,
+    kind: TransactionKind,
+    effective_time: i64,
+  ) -> RosterTransaction {
+    let t = RosterTransaction {
+      id: uuid::Uuid::new_v4().to_string(),
+      league_id: self.id.clone(),
+      team_id: team_id.to_owned(),
+      piece,
+      kind,
+      effective_time,
+      seq: self.transactions.iter().map(|t| t.seq + 1).max().unwrap_or(0),
+    };
+    self.transactions.push(t.clone());
+    self.transactions.sort_by_key(|t| (t.effective_time, t.seq));
+    t
+  }
+
+  pub fn insert_query(&self, dialect: Dialect) -> db::Query {
+    sqlx::query(dialect.insert(
+      "Leagues",
+      &["id", "name", "waiver_mode", "win_points", "scoring"],
+      OnConflict::Fail,
+    ))
+    .bind(self.id.clone())
+    .bind(self.name.clone())
+    .bind(self.waiver_mode.to_string())
+    .bind(self.win_points)
+    .bind(self.scoring.name())
+  }
+
+  pub async fn load(
+    db: &sqlx::Pool<sqlx::Any>,
+    league_id: &str,
+  ) -> Result<League> {
+    let dialect = Dialect::of(db);
+    let row = sqlx::query(dialect.sql(
+      "SELECT name, waiver_mode, win_points, scoring FROM Leagues
+        WHERE id = ?",
+    ))
+    .bind(league_id.to_owned())
+    .fetch_one(db)
+    .await?;
+    let scoring: String = row.try_get("scoring")?;
+    let mut league = League {
+      id: league_id.to_owned(),
+      name: row.try_get("name")?,
+      waiver_mode: row.try_get::<String, _>("waiver_mode")?.parse()?,
+      win_points: row.try_get("win_points")?,
+      scoring: Scoring::from_name(&scoring)
+        .ok_or(Error::UnrecognizedValue("scoring", scoring))?,
+      teams: Vec::new(),
+      transactions: Vec::new(),
+      trades: Vec::new(),
+    };
+
+    let rows = sqlx::query(dialect.sql(
+      "SELECT id, name, waiver_priority, faab_budget FROM Teams
+        WHERE league_id = ? ORDER BY waiver_priority",
+    ))
+    .bind(league_id.to_owned())
+    .fetch_all(db)
+    .await?;
+    for row in rows {
+      league.teams.push(Team {
+        id: row.try_get("id")?,
+        league_id: league_id.to_owned(),
+        name: row.try_get("name")?,
+        waiver_priority: row.try_get("waiver_priority")?,
+        faab_budget: row.try_get("faab_budget")?,
+      });
+    }
+
+    let rows = sqlx::query(dialect.sql(
+      "SELECT id, team_id, source, player_id, piece, kind, effective_time, seq
+        FROM RosterTransactions WHERE league_id = ?
+        ORDER BY effective_time, seq",
+    ))
+    .bind(league_id.to_owned())
+    .fetch_all(db)
+    .await?;
+    for row in rows {
+      league.transactions.push(RosterTransaction {
+        id: row.try_get("id")?,
+        league_id: league_id.to_owned(),
+        team_id: row.try_get("team_id")?,
+        piece: PieceId {
+          source: row.try_get("source")?,
+          player_id: row.try_get("player_id")?,
+          piece: row.try_get("piece")?,
+        },
+        kind: row.try_get::<String, _>("kind")?.parse()?,
+        effective_time: row.try_get("effective_time")?,
+        seq: row.try_get("seq")?,
+      });
+    }
+
+    let rows = sqlx::query(dialect.sql(
+      "SELECT id, proposer_team_id, recipient_team_id, status, proposed_time
+        FROM Trades WHERE league_id = ? ORDER BY proposed_time",
+    ))
+    .bind(league_id.to_owned())
+    .fetch_all(db)
+    .await?;
+    for row in rows {
+      league.trades.push(Trade {
+        id: row.try_get("id")?,
+        league_id: league_id.to_owned(),
+        proposer_team_id: row.try_get("proposer_team_id")?,
+        recipient_team_id: row.try_get("recipient_team_id")?,
+        offered: Vec::new(),
+        requested: Vec::new(),
+        status: row.try_get::<String, _>("status")?.parse()?,
+        proposed_time: row.try_get("proposed_time")?,
+      });
+    }
+
+    let rows = sqlx::query(dialect.sql(
+      "SELECT tp.trade_id, tp.from_team_id, tp.source, tp.player_id, tp.piece
+        FROM TradePieces tp JOIN Trades t ON tp.trade_id = t.id
+        WHERE t.league_id = ?",
+    ))
+    .bind(league_id.to_owned())
+    .fetch_all(db)
+    .await?;
+    for row in rows {
+      let trade_id: String = row.try_get("trade_id")?;
+      let from_team_id: String = row.try_get("from_team_id")?;
+      let piece = PieceId {
+        source: row.try_get("source")?,
+        player_id: row.try_get("player_id")?,
+        piece: row.try_get("piece")?,
+      };
+      if let Some(trade) = league.trades.iter_mut().find(|t| t.id == trade_id) {
+        if from_team_id == trade.proposer_team_id {
+          trade.offered.push(piece);
+        } else {
+          trade.requested.push(piece);
+        }
+      }
+    }
+    Ok(league)
+  }
+}
+
+impl Team {
+  // Inserts the team, or updates it in place if it already exists (e.g. after
+  // waivers change its priority or budget).
+  pub fn upsert_query(&self, dialect: Dialect) -> db::Query {
+    sqlx::query(dialect.insert(
+      "Teams",
+      &["id", "league_id", "name", "waiver_priority", "faab_budget"],
+      OnConflict::Update(&["id"]),
+    ))
+    .bind(self.id.clone())
+    .bind(self.league_id.clone())
+    .bind(self.name.clone())
+    .bind(self.waiver_priority)
+    .bind(self.faab_budget)
+  }
+}
+
+impl RosterTransaction {
+  pub fn insert_query(&self, dialect: Dialect) -> db::Query {
+    sqlx::query(dialect.insert(
+      "RosterTransactions",
+      &[
+        "id",
+        "league_id",
+        "team_id",
+        "source",
+        "player_id",
+        "piece",
+        "kind",
+        "effective_time",
+        "seq",
+      ],
+      OnConflict::Fail,
+    ))
+    .bind(self.id.clone())
+    .bind(self.league_id.clone())
+    .bind(self.team_id.clone())
+    .bind(self.piece.source.clone())
+    .bind(self.piece.player_id.clone())
+    .bind(self.piece.piece.clone())
+    .bind(self.kind.to_string())
+    .bind(self.effective_time)
+    .bind(self.seq)
+  }
+}
+
+impl Trade {
+  pub fn accept(&mut self) -> Result<()> {
+    self.check_status(TradeStatus::Proposed)?;
+    self.status = TradeStatus::Accepted;
+    Ok(())
+  }
+
+  pub fn reject(&mut self) -> Result<()> {
+    self.check_status(TradeStatus::Proposed)?;
+    self.status = TradeStatus::Rejected;
+    Ok(())
+  }
+
+  // Accepted trades sit in review until executed; the commissioner may veto
+  // them during that time.
+  pub fn veto(&mut self) -> Result<()> {
+    self.check_status(TradeStatus::Accepted)?;
+    self.status = TradeStatus::Vetoed;
+    Ok(())
+  }
+
+  fn check_status(&self, expected: TradeStatus) -> Result<()> {
+    if self.status != expected {
+      return Err(Error::InvalidTradeState {
+        id: self.id.clone(),
+        status: self.status,
+        expected,
+      });
+    }
+    Ok(())
+  }
+
+  pub fn insert_queries(&self, dialect: Dialect) -> Vec<db::Query> {
+    let mut inserts = vec![sqlx::query(dialect.insert(
+      "Trades",
+      &[
+        "id",
+        "league_id",
+        "proposer_team_id",
+        "recipient_team_id",
+        "status",
+        "proposed_time",
+      ],
+      OnConflict::Fail,
+    ))
+    .bind(self.id.clone())
+    .bind(self.league_id.clone())
+    .bind(self.proposer_team_id.clone())
+    .bind(self.recipient_team_id.clone())
+    .bind(self.status.to_string())
+    .bind(self.proposed_time)];
+    for (from, pieces) in [
+      (&self.proposer_team_id, &self.offered),
+      (&self.recipient_team_id, &self.requested),
+    ]
+    .iter()
+    {
+      for piece in pieces.iter() {
+        inserts.push(
+          sqlx::query(dialect.insert(
+            "TradePieces",
+            &["trade_id", "from_team_id", "source", "player_id", "piece"],
+            OnConflict::Fail,
+          ))
+          .bind(self.id.clone())
+          .bind(from.to_string())
+          .bind(piece.source.clone())
+          .bind(piece.player_id.clone())
+          .bind(piece.piece.clone()),
+        );
+      }
+    }
+    inserts
+  }
+
+  pub fn update_status_query(&self, dialect: Dialect) -> db::Query {
+    sqlx::query(dialect.sql("UPDATE Trades SET status = ? WHERE id = ?"))
+      .bind(self.status.to_string())
+      .bind(self.id.clone())
+  }
+}
+
+impl WaiverClaim {
+  pub fn insert_query(&self, dialect: Dialect) -> db::Query {
+    sqlx::query(dialect.insert(
+      "WaiverClaims",
+      &[
+        "id",
+        "league_id",
+        "team_id",
+        "source",
+        "player_id",
+        "piece",
+        "drop_source",
+        "drop_player_id",
+        "drop_piece",
+        "bid",
+        "submitted_time",
+      ],
+      OnConflict::Fail,
+    ))
+    .bind(self.id.clone())
+    .bind(self.league_id.clone())
+    .bind(self.team_id.clone())
+    .bind(self.add.source.clone())
+    .bind(self.add.player_id.clone())
+    .bind(self.add.piece.clone())
+    .bind(self.drop.as_ref().map(|d| d.source.clone()))
+    .bind(self.drop.as_ref().map(|d| d.player_id.clone()))
+    .bind(self.drop.as_ref().map(|d| d.piece.clone()))
+    .bind(self.bid)
+    .bind(self.submitted_time)
+  }
+
+  pub fn delete_query(&self, dialect: Dialect) -> db::Query {
+    sqlx::query(dialect.sql("DELETE FROM WaiverClaims WHERE id = ?"))
+      .bind(self.id.clone())
+  }
+}
+
+// Returns the league's claims still waiting for a waiver run, oldest first.
+pub async fn waiver_claims(
+  db: &sqlx::Pool<sqlx::Any>,
+  league_id: &str,
+) -> Result<Vec<WaiverClaim>> {
+  let rows = sqlx::query(Dialect::of(db).sql(
+    "SELECT id, team_id, source, player_id, piece, drop_source,
+        drop_player_id, drop_piece, bid, submitted_time
+      FROM WaiverClaims WHERE league_id = ? ORDER BY submitted_time",
+  ))
+  .bind(league_id.to_owned())
+  .fetch_all(db)
+  .await?;
+  rows
+    .iter()
+    .map(|row| {
+      let drop_source: Option<String> = row.try_get("drop_source")?;
+      let drop = match drop_source {
+        Some(source) => Some(PieceId {
+          source,
+          player_id: row.try_get("drop_player_id")?,
+          piece: row.try_get("drop_piece")?,
+        }),
+        None => None,
+      };
+      Ok(WaiverClaim {
+        id: row.try_get("id")?,
+        league_id: league_id.to_owned(),
+        team_id: row.try_get("team_id")?,
+        add: PieceId {
+          source: row.try_get("source")?,
+          player_id: row.try_get("player_id")?,
+          piece: row.try_get("piece")?,
+        },
+        drop,
+        bid: row.try_get("bid")?,
+        submitted_time: row.try_get("submitted_time")?,
+      })
+    })
+    .collect()
+}
+
+// Two teams' scores over the same period. Every pair of teams in a league
+// meets once per period.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Matchup {
+  pub home: Team,
+  pub away: Team,
+  pub home_score: i64,
+  pub away_score: i64,
+}
+
+// Sums the capture and win points scored by a team's pieces, counting only
+// games that ended while the team held the piece.
+pub async fn team_score(
+  db: &sqlx::Pool<sqlx::Any>,
+  league: &League,
+  team_id: &str,
+) -> Result<i64> {
+  period_score(db, league, team_id, i64::MIN, None).await
+}
+
+// Like team_score, but only counting games that ended in [start, end).
+pub async fn period_score(
+  db: &sqlx::Pool<sqlx::Any>,
+  league: &League,
+  team_id: &str,
+  start: i64,
+  end: Option<i64>,
+) -> Result<i64> {
+  let mut score = 0;
+  for mut window in league.holding_windows(team_id) {
+    window.start = window.start.max(start);
+    window.end = match (window.end, end) {
+      (Some(a), Some(b)) => Some(a.min(b)),
+      (a, b) => a.or(b),
+    };
+    if matches!(window.end, Some(end) if end <= window.start) {
+      continue;
+    }
+    score += window_score(db, &window, league.scoring).await?;
+    score += league.win_points as i64 * window_wins(db, &window).await?;
+  }
+  Ok(score)
+}
+
+// Pairs every team against every other team, in waiver priority order, scored
+// over [start, end).
+pub async fn matchups(
+  db: &sqlx::Pool<sqlx::Any>,
+  league: &League,
+  start: i64,
+  end: Option<i64>,
+) -> Result<Vec<Matchup>> {
+  let mut scores = Vec::new();
+  for team in league.teams.iter() {
+    scores.push(period_score(db, league, &team.id, start, end).await?);
+  }
+  Ok(
+    league
+      .teams
+      .iter()
+      .zip(scores)
+      .tuple_combinations()
+      .map(|((home, home_score), (away, away_score))| Matchup {
+        home: home.clone(),
+        away: away.clone(),
+        home_score,
+        away_score,
+      })
+      .collect(),
+  )
+}
+
+pub async fn standings(
+  db: &sqlx::Pool<sqlx::Any>,
+  league: &League,
+) -> Result<Vec<(Team, i64)>> {
+  let mut standings = Vec::new();
+  for team in league.teams.iter() {
+    standings.push((team.clone(), team_score(db, league, &team.id).await?));
+  }
+  standings.sort_by_key(|(_, score)| Reverse(*score));
+  Ok(standings)
+}
+
+// Sums the points scored by the piece's captures in the window. Opponent
+// ratings are the ones taken into each game, as in stats::Scoring.
+async fn window_score(
+  db: &sqlx::Pool<sqlx::Any>,
+  window: &HoldingWindow,
+  scoring: Scoring,
+) -> Result<i64> {
+  let query = if window.piece.source == PERSON_SOURCE {
+    sqlx::query(Dialect::of(db).sql(
+      "SELECT m.capture_score, CASE WHEN m.color = 'white'
+          THEN g.black_player_rating ELSE g.white_player_rating END
+          AS opponent_rating
+        FROM Moves m JOIN Games g ON m.game_id = g.id
+        JOIN Players p ON p.source = g.source
+          AND ((m.color = 'white' AND p.external_id = g.white_player_id)
+            OR (m.color = 'black' AND p.external_id = g.black_player_id))
+        WHERE p.person_id = ? AND m.moved_piece = ?
+          AND g.end_time >= ? AND g.end_time < ?",
+    ))
+    .bind(window.piece.player_id.clone())
+    .bind(window.piece.piece.clone())
+  } else {
+    sqlx::query(Dialect::of(db).sql(
+      "SELECT m.capture_score, CASE WHEN m.color = 'white'
+          THEN g.black_player_rating ELSE g.white_player_rating END
+          AS opponent_rating
+        FROM Moves m JOIN Games g ON m.game_id = g.id
+        WHERE g.source = ? AND m.moved_piece = ?
+          AND ((m.color = 'white' AND g.white_player_id = ?)
+            OR (m.color = 'black' AND g.black_player_id = ?))
+          AND g.end_time >= ? AND g.end_time < ?",
+    ))
+    .bind(window.piece.source.clone())
+    .bind(window.piece.piece.clone())
+    .bind(window.piece.player_id.clone())
+    .bind(window.piece.player_id.clone())
+  };
+  let rows = query
+    .bind(window.start)
+    .bind(window.end.unwrap_or(i64::MAX))
+    .fetch_all(db)
+    .await?;
+  Ok(
+    rows
+      .iter()
+      .map(|r| {
+        Ok(
+          scoring
+            .points(r.try_get("capture_score")?, r.try_get("opponent_rating")?),
+        )
+      })
+      .sum::<sqlx::Result<i64>>()?,
+  )
+}
+
+// Counts the games in the window that the piece played and its side won.
+// Pieces that never spawned, as in odds games, have no lifecycle and so don't
+// share in the win.
+async fn window_wins(
+  db: &sqlx::Pool<sqlx::Any>,
+  window: &HoldingWindow,
+) -> Result<i64> {
+  let query = if window.piece.source == PERSON_SOURCE {
+    sqlx::query(Dialect::of(db).sql(
+      "SELECT COUNT(*) AS wins FROM PieceLifecycles l
+        JOIN Games g ON l.game_id = g.id
+        JOIN Players p ON p.source = g.source
+          AND ((l.color = 'white' AND p.external_id = g.white_player_id
+              AND g.result = '1-0')
+            OR (l.color = 'black' AND p.external_id = g.black_player_id
+              AND g.result = '0-1'))
+        WHERE p.person_id = ? AND l.piece = ?
+          AND g.end_time >= ? AND g.end_time < ?",
+    ))
+    .bind(window.piece.player_id.clone())
+    .bind(window.piece.piece.clone())
+  } else {
+    sqlx::query(Dialect::of(db).sql(
+      "SELECT COUNT(*) AS wins FROM PieceLifecycles l
+        JOIN Games g ON l.game_id = g.id
+        WHERE g.source = ? AND l.piece = ?
+          AND ((l.color = 'white' AND g.white_player_id = ?
+              AND g.result = '1-0')
+            OR (l.color = 'black' AND g.black_player_id = ?
+              AND g.result = '0-1'))
+          AND g.end_time >= ? AND g.end_time < ?",
+    ))
+    .bind(window.piece.source.clone())
+    .bind(window.piece.piece.clone())
+    .bind(window.piece.player_id.clone())
+    .bind(window.piece.player_id.clone())
+  };
+  let row = query
+    .bind(window.start)
+    .bind(window.end.unwrap_or(i64::MAX))
+    .fetch_one(db)
+    .await?;
+  Ok(row.try_get("wins")?)
+}
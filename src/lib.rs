@@ -1,7 +1,17 @@
+pub mod api;
 pub mod chess_com;
+pub mod config;
 pub mod db;
 pub mod dumbchess;
+pub mod eval;
+pub mod export;
+pub mod heatmap;
+pub mod league;
+pub mod metrics;
+pub mod openings;
 pub mod pgn;
+pub mod stats;
+pub mod uci;
 
 #[macro_use]
 extern crate lazy_static;
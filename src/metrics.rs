@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::db;
+
+// Progress is redrawn at most this often.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+// Running totals for an ingest, shared between the reader, the parser and the
+// insert workers.
+pub struct Metrics {
+  start: Instant,
+  total_bytes: u64,
+  bytes_read: Arc<AtomicU64>,
+  counts: Mutex<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+  games: u64,
+  inserts: u64,
+  // Rejected games by category, then reason
+  rejected: BTreeMap<&'static str, BTreeMap<String, u64>>,
+  last_redraw: Option<Instant>,
+}
+
+// The final report, also written out as JSON for monitoring.
+#[derive(Debug, serde::Serialize)]
+pub struct Summary {
+  pub games: u64,
+  pub inserts: u64,
+  pub bytes_read: u64,
+  pub total_bytes: u64,
+  pub elapsed_secs: f64,
+  pub games_per_sec: f64,
+  // Counts by reason within each category
+  pub skipped: BTreeMap<String, u64>,
+  pub nonstandard: BTreeMap<String, u64>,
+  pub failed: BTreeMap<String, u64>,
+  // Share of the games read that weren't recorded, from 0 to 1
+  pub reject_rate: f64,
+}
+
+// Wraps a reader, adding the bytes read from it to the ingest's total.
+pub struct CountingReader<R> {
+  inner: R,
+  count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    self.count.fetch_add(n as u64, Ordering::Relaxed);
+    Ok(n)
+  }
+}
+
+impl Metrics {
+  // `total_bytes` is the combined size of the inputs, used to estimate how
+  // far along the ingest is.
+  pub fn new(total_bytes: u64) -> Metrics {
+    Metrics {
+      start: Instant::now(),
+      total_bytes,
+      bytes_read: Arc::new(AtomicU64::new(0)),
+      counts: Mutex::new(Counts::default()),
+    }
+  }
+
+  pub fn reader<R: Read>(&self, inner: R) -> CountingReader<R> {
+    CountingReader { inner, count: self.bytes_read.clone() }
+  }
+
+  pub fn record_game(&self, inserts: usize) {
+    let mut counts = self.counts.lock().unwrap();
+    counts.games += 1;
+    counts.inserts += inserts as u64;
+  }
+
+  pub fn record_rejection(&self, rejection: &db::Rejection) {
+    let mut counts = self.counts.lock().unwrap();
+    *counts
+      .rejected
+      .entry(rejection.category())
+      .or_default()
+      .entry(rejection.reason())
+      .or_default() += 1;
+  }
+
+  // Whether enough time has passed since the progress line was last drawn to
+  // draw it again.
+  pub fn should_redraw(&self) -> bool {
+    let mut counts = self.counts.lock().unwrap();
+    let now = Instant::now();
+    match counts.last_redraw {
+      Some(last) if now - last < REDRAW_INTERVAL => false,
+      _ => {
+        counts.last_redraw = Some(now);
+        true
+      }
+    }
+  }
+
+  pub fn summary(&self) -> Summary {
+    let counts = self.counts.lock().unwrap();
+    let elapsed = self.start.elapsed().as_secs_f64();
+    let category =
+      |name| counts.rejected.get(name).cloned().unwrap_or_default();
+    let rejected: u64 = counts.rejected.values().flat_map(|r| r.values()).sum();
+    let seen = counts.games + rejected;
+    Summary {
+      games: counts.games,
+      inserts: counts.inserts,
+      bytes_read: self.bytes_read.load(Ordering::Relaxed),
+      total_bytes: self.total_bytes,
+      elapsed_secs: elapsed,
+      games_per_sec: if elapsed > 0.0 {
+        counts.games as f64 / elapsed
+      } else {
+        0.0
+      },
+      skipped: category(db::Rejection::SKIPPED),
+      nonstandard: category(db::Rejection::NONSTANDARD),
+      failed: category(db::Rejection::FAILED),
+      reject_rate: if seen > 0 { rejected as f64 / seen as f64 } else { 0.0 },
+    }
+  }
+}
+
+impl Summary {
+  fn rejected(&self) -> u64 {
+    [&self.skipped, &self.nonstandard, &self.failed]
+      .iter()
+      .flat_map(|c| c.values())
+      .sum()
+  }
+
+  // Estimated time left, from how quickly the input has been read so far.
+  fn eta(&self) -> Option<Duration> {
+    if self.bytes_read == 0 || self.elapsed_secs == 0.0 {
+      return None;
+    }
+    let rate = self.bytes_read as f64 / self.elapsed_secs;
+    let remaining = self.total_bytes.saturating_sub(self.bytes_read) as f64;
+    Some(Duration::from_secs_f64(remaining / rate))
+  }
+
+  fn percent(&self) -> f64 {
+    if self.total_bytes == 0 {
+      100.0
+    } else {
+      100.0 * self.bytes_read as f64 / self.total_bytes as f64
+    }
+  }
+
+  // One line of live progress.
+  pub fn progress_line(&self) -> String {
+    format!(
+      "GAMES: {} ({:.0}/s)\tINSERTS: {}\tREAD: {} / {} ({:.1}%)\tREJECTED: {}\tETA: {}",
+      self.games,
+      self.games_per_sec,
+      self.inserts,
+      format_bytes(self.bytes_read),
+      format_bytes(self.total_bytes),
+      self.percent(),
+      self.rejected(),
+      self.eta().map(format_duration).unwrap_or_else(|| "-".into()),
+    )
+  }
+
+  // The end-of-run report, one item per line.
+  pub fn report(&self) -> String {
+    let mut report = format!(
+      "recorded {} games ({} inserts) in {} ({:.0} games/s)\n\
+       read {} of {}\n\
+       rejected {} games ({:.1}%)\n",
+      self.games,
+      self.inserts,
+      format_duration(Duration::from_secs_f64(self.elapsed_secs)),
+      self.games_per_sec,
+      format_bytes(self.bytes_read),
+      format_bytes(self.total_bytes),
+      self.rejected(),
+      100.0 * self.reject_rate,
+    );
+    for (category, reasons) in [
+      (db::Rejection::SKIPPED, &self.skipped),
+      (db::Rejection::NONSTANDARD, &self.nonstandard),
+      (db::Rejection::FAILED, &self.failed),
+    ]
+    .iter()
+    {
+      for (reason, count) in reasons.iter() {
+        report.push_str(&format!("  {} {}: {}\n", category, reason, count));
+      }
+    }
+    report
+  }
+}
+
+fn format_bytes(bytes: u64) -> String {
+  const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+  let mut value = bytes as f64;
+  let mut unit = 0;
+  while value >= 1024.0 && unit < UNITS.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{} {}", bytes, UNITS[0])
+  } else {
+    format!("{:.1} {}", value, UNITS[unit])
+  }
+}
+
+fn format_duration(d: Duration) -> String {
+  let secs = d.as_secs();
+  if secs >= 3600 {
+    format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+  } else if secs >= 60 {
+    format!("{}m{:02}s", secs / 60, secs % 60)
+  } else {
+    format!("{}s", secs)
+  }
+}
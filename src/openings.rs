@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use crate::db;
+use crate::dumbchess::Board;
+
+// Opening lines in the format of lichess's chess-openings tables (a.tsv to
+// e.tsv, one per ECO volume): tab-separated ECO code, name, and the line as
+// numbered SAN movetext. The first row of each is a header. Newer upstream
+// tables can be dropped in as-is.
+const BOOK_TSVS: [&str; 5] = [
+  include_str!("../data/openings/a.tsv"),
+  include_str!("../data/openings/b.tsv"),
+  include_str!("../data/openings/c.tsv"),
+  include_str!("../data/openings/d.tsv"),
+  include_str!("../data/openings/e.tsv"),
+];
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Opening {
+  pub eco: &'static str,
+  pub name: &'static str,
+}
+
+struct Book {
+  // Keyed by the line's moves in UCI notation, separated by spaces
+  lines: HashMap<String, Opening>,
+  // Number of moves in the longest line
+  max_len: usize,
+}
+
+lazy_static! {
+  static ref BOOK: Book = {
+    let mut lines = HashMap::new();
+    let mut max_len = 0;
+    for row in BOOK_TSVS
+      .iter()
+      .flat_map(|tsv| tsv.lines().skip(1))
+      .filter(|r| !r.trim().is_empty())
+    {
+      let mut fields = row.split('\t');
+      let (eco, name, pgn) = match (fields.next(), fields.next(), fields.next())
+      {
+        (Some(eco), Some(name), Some(pgn)) => (eco, name, pgn),
+        _ => panic!("malformed opening book row: {}", row),
+      };
+      let moves = uci_line(pgn)
+        .unwrap_or_else(|| panic!("illegal opening book line: {}", row));
+      max_len = max_len.max(moves.len());
+      lines.insert(moves.join(" "), Opening { eco, name });
+    }
+    Book { lines, max_len }
+  };
+}
+
+// Plays out a line of movetext such as "1. e4 e5 2. Nf3", returning its moves
+// in UCI notation.
+fn uci_line(pgn: &str) -> Option<Vec<String>> {
+  let mut board = Board::starting();
+  pgn
+    .split_whitespace()
+    .filter(|token| !token.ends_with('.'))
+    .map(|san| board.make_san_move(san).ok().map(|m| m.uci))
+    .collect()
+}
+
+// Finds the most specific book line that the game's moves (in UCI notation)
+// start with.
+pub fn classify<S: AsRef<str>>(moves: &[S]) -> Option<Opening> {
+  let mut line = String::new();
+  let mut best = None;
+  for m in moves.iter().take(BOOK.max_len) {
+    if !line.is_empty() {
+      line.push(' ');
+    }
+    line.push_str(m.as_ref());
+    if let Some(opening) = BOOK.lines.get(line.as_str()) {
+      best = Some(*opening);
+    }
+  }
+  best
+}
+
+// Fills in a game's ECO code and opening name from its moves, unless the
+// source already named the opening. A bare ECO code from the source is kept
+// if the book doesn't recognize the line.
+pub fn classify_game(game: &mut db::Game, moves: &[db::Move]) {
+  if !game.opening.is_empty() {
+    return;
+  }
+  let uci: Vec<String> = moves.iter().map(db::Move::uci_move).collect();
+  if let Some(opening) = classify(&uci) {
+    game.eco = opening.eco.to_owned();
+    game.opening = opening.name.to_owned();
+  }
+}
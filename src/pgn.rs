@@ -1,73 +1,172 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
 use crate::db;
 use crate::dumbchess;
+use crate::eval;
+use crate::openings;
 use itertools::Itertools;
-use minorhacks_chess as chess;
 
-#[derive(Clone)]
+// Narrows down which games get ingested. Games are judged on their headers
+// alone, so the moves of games that don't match are never parsed.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderFilter {
+  // Lowercased account names; a game matches if either player is listed
+  pub players: Option<HashSet<String>>,
+  // Unix timestamps; since is inclusive and until is exclusive
+  pub since: Option<i64>,
+  pub until: Option<i64>,
+  // Both players must be rated at least this
+  pub min_rating: Option<i32>,
+  // Any time class matches when empty
+  pub time_classes: Vec<db::TimeClass>,
+  // Drops games marked casual. Sources that don't mark games either way, like
+  // chess.com's "Live Chess" events, are taken to be rated.
+  pub rated_only: bool,
+  // What to do with games of variants that can't be replayed
+  pub unsupported_variants: VariantPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VariantPolicy {
+  // Drop the game, counting it as nonstandard
+  #[default]
+  Skip,
+  // Store the game's metadata, but none of its moves or pieces
+  Metadata,
+}
+
+impl HeaderFilter {
+  // Reads a list of account names, one per line. Blank lines and lines
+  // starting with # are ignored.
+  pub fn read_players(path: &str) -> std::io::Result<HashSet<String>> {
+    Ok(
+      std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect(),
+    )
+  }
+
+  // Returns why the game doesn't match, if it doesn't.
+  fn reject(&self, game: &db::Game, rated: Option<bool>) -> Option<String> {
+    if let Some(players) = &self.players {
+      if !players.contains(&game.white_player_id.to_lowercase())
+        && !players.contains(&game.black_player_id.to_lowercase())
+      {
+        return Some(String::from("player"));
+      }
+    }
+    if matches!(self.since, Some(since) if game.end_time < since)
+      || matches!(self.until, Some(until) if game.end_time >= until)
+    {
+      return Some(String::from("date"));
+    }
+    if let Some(min_rating) = self.min_rating {
+      if game.white_player_rating.min(game.black_player_rating) < min_rating {
+        return Some(String::from("rating"));
+      }
+    }
+    if !self.time_classes.is_empty() {
+      match game.time_class() {
+        Some(class) if self.time_classes.contains(&class) => (),
+        _ => return Some(String::from("time_class")),
+      }
+    }
+    if self.rated_only && rated == Some(false) {
+      return Some(String::from("unrated"));
+    }
+    None
+  }
+}
+
 pub struct GameScore {
   game: db::Game,
   moves: Vec<db::Move>,
-  board: chess::Board,
-  dumbboard: dumbchess::Board,
+  board: dumbchess::Board,
+  filter: Arc<HeaderFilter>,
 
   date: String,
   time: String,
+  // From the Event header; unknown for sources that don't say
+  rated: Option<bool>,
+  // Starting position, for games that don't start from the usual one
+  fen: Option<String>,
+  // The Termination header, if there is one
+  termination: String,
 
-  nonstandard_game: bool,
+  // Why the filter rejected the game, if it did
+  skipped: Option<String>,
   move_count: u32,
+  // The first thing that went wrong translating the game, if anything did.
+  // The rest of the game is skipped once this is set.
+  error: Option<db::Error>,
 }
 
 impl GameScore {
   pub fn new() -> GameScore {
+    GameScore::with_filter(Arc::default())
+  }
+
+  pub fn with_filter(filter: Arc<HeaderFilter>) -> GameScore {
     GameScore {
       game: db::Game::empty(),
       moves: Vec::new(),
-      board: chess::Board::default(),
-      dumbboard: dumbchess::Board::starting(),
+      board: dumbchess::Board::starting(),
+      filter,
 
       date: String::new(),
       time: String::new(),
+      rated: None,
+      fen: None,
+      termination: String::new(),
 
-      nonstandard_game: false,
+      skipped: None,
       move_count: 0,
+      error: None,
     }
   }
-}
 
-impl Default for GameScore {
-  fn default() -> Self {
-    Self::new()
+  fn fail(&mut self, error: db::Error) {
+    if self.error.is_none() {
+      self.error = Some(error);
+    }
   }
-}
 
-impl db::Recordable for GameScore {
-  fn game(&self) -> db::Result<db::Game> {
-    Ok(self.game.clone())
+  fn invalid_header(&mut self, name: &str, value: &str) {
+    self.fail(db::Error::InvalidHeader {
+      name: name.to_owned(),
+      value: value.to_owned(),
+    });
   }
+}
 
-  fn moves(&self) -> db::Result<Vec<db::Move>> {
-    Ok(self.moves.clone())
+impl Default for GameScore {
+  fn default() -> Self {
+    Self::new()
   }
 }
 
 impl pgn_reader::Visitor for GameScore {
-  type Result = Option<Self>;
+  type Result = Result<db::GameRecord, db::Rejection>;
 
   fn begin_variation(&mut self) -> pgn_reader::Skip {
     pgn_reader::Skip(true)
   }
 
   fn header(&mut self, key: &[u8], value: pgn_reader::RawHeader<'_>) {
-    let value = value
-      .decode_utf8()
-      .expect("invalid UTF-8 in PGN header value")
-      .to_string();
-
-    match std::str::from_utf8(key)
-      .expect("invalid UTF-8 in PGN header key")
-      .to_lowercase()
-      .as_str()
-    {
+    let key = String::from_utf8_lossy(key);
+    let value = match value.decode_utf8() {
+      Ok(value) => value.to_string(),
+      Err(_) => {
+        let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+        return self.invalid_header(&key, &value);
+      }
+    };
+
+    match key.to_lowercase().as_str() {
       "white" => {
         self.game.white_player_name = value.clone();
         self.game.white_player_id = value;
@@ -76,16 +175,17 @@ impl pgn_reader::Visitor for GameScore {
         self.game.black_player_name = value.clone();
         self.game.black_player_id = value;
       }
-      "whiteelo" => {
-        self.game.white_player_rating = value
-          .parse::<i32>()
-          .unwrap_or_else(|_| panic!("can't parse rating: {}", value));
-      }
-      "blackelo" => {
-        self.game.black_player_rating = value
-          .parse::<i32>()
-          .unwrap_or_else(|_| panic!("can't parse rating: {}", value));
-      }
+      "timecontrol" => self.game.time_control = value,
+      "eco" => self.game.eco = value,
+      "opening" => self.game.opening = value,
+      "whiteelo" => match value.parse::<i32>() {
+        Ok(rating) => self.game.white_player_rating = rating,
+        Err(_) => self.invalid_header(&key, &value),
+      },
+      "blackelo" => match value.parse::<i32>() {
+        Ok(rating) => self.game.black_player_rating = rating,
+        Err(_) => self.invalid_header(&key, &value),
+      },
       // TODO: lichess provides UTCDate and UTCTime
       "utcdate" => self.date = value,
       "utctime" => self.time = value,
@@ -97,10 +197,9 @@ impl pgn_reader::Visitor for GameScore {
       }
       "site" => {
         // TODO: These site-specific details don't belong here
-        if value.starts_with("https://lichess.org/") {
+        if let Some(id) = value.strip_prefix("https://lichess.org/") {
           self.game.source = String::from("lichess.org");
-          self.game.source_id =
-            value.strip_prefix("https://lichess.org/").unwrap().to_string();
+          self.game.source_id = id.to_string();
         } else {
           self.game.source = value.to_lowercase();
         }
@@ -114,13 +213,22 @@ impl pgn_reader::Visitor for GameScore {
         }
       }
       "event" => {
-        if value.to_lowercase().contains("odds chess") {
-          self.nonstandard_game = true;
-        }
-        if value.to_lowercase().contains("chess960") {
-          self.nonstandard_game = true;
+        // lichess events read e.g. "Rated Blitz game" or "Casual Rapid game"
+        if value.starts_with("Rated ") {
+          self.rated = Some(true);
+        } else if value.starts_with("Casual ") {
+          self.rated = Some(false);
         }
       }
+      "result" => match value.as_str() {
+        db::WHITE_WON | db::BLACK_WON | db::DRAWN | db::UNKNOWN_RESULT => {
+          self.game.result = value
+        }
+        _ => self.invalid_header(&key, &value),
+      },
+      "termination" => self.termination = value,
+      "variant" => self.game.variant = variant_name(&value),
+      "fen" => self.fen = Some(value),
       _ => (),
     }
   }
@@ -128,128 +236,209 @@ impl pgn_reader::Visitor for GameScore {
   fn end_headers(&mut self) -> pgn_reader::Skip {
     self.game.id = uuid::Uuid::new_v4().to_string();
     let date_time = format!("{} {}", self.date, self.time);
-    self.game.end_time =
-      chrono::NaiveDateTime::parse_from_str(&date_time, "%Y.%m.%d %H:%M:%S")
-        .unwrap_or_else(|_| panic!("invalid date/time: {}", date_time))
-        .timestamp();
-    pgn_reader::Skip(self.nonstandard_game)
+    match chrono::NaiveDateTime::parse_from_str(&date_time, "%Y.%m.%d %H:%M:%S")
+    {
+      Ok(end_time) => self.game.end_time = end_time.timestamp(),
+      Err(_) => self.invalid_header("date/time", &date_time),
+    }
+    // Odds games start from the usual position with pieces removed. lichess
+    // marks games set up from a position as From Position, while chess.com
+    // only adds a FEN header.
+    if let Some(fen) = &self.fen {
+      if self.game.variant == db::STANDARD_VARIANT
+        || self.game.variant == FROM_POSITION
+      {
+        match dumbchess::Board::from_fen(fen) {
+          Ok(board) if board.fen() == dumbchess::Board::starting().fen() => {
+            self.game.variant = db::STANDARD_VARIANT.to_owned();
+          }
+          Ok(board) => {
+            self.board = board;
+            self.game.variant = db::ODDS_VARIANT.to_owned();
+            self.game.starting_fen = Some(fen.clone());
+          }
+          Err(_) => self.game.variant = FROM_POSITION.to_owned(),
+        }
+      }
+    }
+    if self.error.is_none() {
+      self.skipped = self.filter.reject(&self.game, self.rated);
+    }
+    pgn_reader::Skip(
+      !self.game.is_replayable()
+        || self.skipped.is_some()
+        || self.error.is_some(),
+    )
   }
 
   fn san(&mut self, san_plus: pgn_reader::SanPlus) {
+    if self.error.is_some() {
+      return;
+    }
     self.move_count += 1;
-    if let Ok(m) =
-      chess::ChessMove::from_san(&self.board, &san_plus.to_string())
-    {
-      let db_move = self
-        .dumbboard
-        .make_move(
-          &dumbchess_square(m.get_source()),
-          &dumbchess_square(m.get_dest()),
-          promotion_value(m.get_promotion()),
-        )
-        .unwrap_or_else(|_| {
-          panic!(
-            "invalid move {} on dumbchess board in game {}: {:?}",
-            self.move_count, self.game.source_id, m
-          )
-        });
-      let mut old_board = chess::Board::default();
-      std::mem::swap(&mut old_board, &mut self.board);
-      old_board.make_move(m, &mut self.board);
-      self.moves.push(db_move);
-    } else {
-      panic!(
-        "invalid move {}: {}. Board: {:?}",
-        self.move_count, san_plus, self.board
-      );
+    let san = san_plus.to_string();
+    match self.board.make_san_move(&san) {
+      Ok(db_move) => self.moves.push(db_move),
+      Err(source) => {
+        self.fail(db::Error::InvalidMove { ply: self.move_count, san, source })
+      }
+    }
+  }
+
+  // lichess and chess.com annotate each move with the clock in a comment
+  // following it, e.g. { [%clk 0:02:58.1] } or { [%emt 0:00:01.9] }.
+  // Analyzed lichess games also carry the engine's evaluation of the
+  // resulting position, e.g. { [%eval 0.17] } or { [%eval #-3] }.
+  fn comment(&mut self, comment: pgn_reader::RawComment<'_>) {
+    if let Some(last_move) = self.moves.last_mut() {
+      let comment = String::from_utf8_lossy(comment.as_bytes());
+      if let Some(clock) = command(&comment, "clk").and_then(parse_clock) {
+        last_move.clock_ms = Some(clock);
+      }
+      if let Some(elapsed) = command(&comment, "emt").and_then(parse_clock) {
+        last_move.elapsed_ms = Some(elapsed);
+      }
+      if let Some(Ok(eval)) = command(&comment, "eval").map(str::parse) {
+        last_move.set_eval(eval);
+      }
     }
   }
 
   fn end_game(&mut self) -> Self::Result {
-    if self.nonstandard_game {
+    // Games the filter skipped count as skipped whatever their variant.
+    if let Some(reason) = self.skipped.take() {
+      return Err(db::Rejection::Skipped(reason));
+    }
+    let supported = self.game.is_replayable();
+    if !supported && self.filter.unsupported_variants == VariantPolicy::Skip {
+      return Err(db::Rejection::Nonstandard(self.game.variant.clone()));
+    }
+    if let Some(error) = self.error.take() {
+      return Err(error.into());
+    }
+    let mut game = std::mem::replace(&mut self.game, db::Game::empty());
+    let ending = if supported { self.board.ending() } else { None };
+    game.termination = db::termination(&self.termination, &game.result, ending);
+    if !supported {
+      return Ok(db::GameRecord {
+        game,
+        moves: Vec::new(),
+        lifecycles: Vec::new(),
+      });
+    }
+    let mut moves = std::mem::take(&mut self.moves);
+    eval::annotate(&mut moves);
+    game.white_accuracy = eval::accuracy(&moves, "white");
+    game.black_accuracy = eval::accuracy(&moves, "black");
+    if game.variant == db::STANDARD_VARIANT {
+      openings::classify_game(&mut game, &moves);
+    }
+    Ok(db::GameRecord { game, moves, lifecycles: self.board.lifecycles() })
+  }
+}
+
+// Splits a PGN database into chunks of whole games, so that each chunk can be
+// parsed independently of the others.
+pub struct GameChunks<R> {
+  reader: R,
+  games_per_chunk: usize,
+  // First line of the next chunk, read while finding the end of the last one
+  pending: Vec<u8>,
+  // Set after a read error, since the reader can't be trusted past it
+  failed: bool,
+}
+
+impl<R: std::io::BufRead> GameChunks<R> {
+  pub fn new(reader: R, games_per_chunk: usize) -> GameChunks<R> {
+    GameChunks { reader, games_per_chunk, pending: Vec::new(), failed: false }
+  }
+}
+
+impl<R: std::io::BufRead> Iterator for GameChunks<R> {
+  type Item = std::io::Result<Vec<u8>>;
+
+  // A game ends where a header line follows its movetext. Brackets inside a
+  // multi-line comment (e.g. a `[%clk ...]` command) don't count as headers.
+  // Comments don't nest, so a `{` inside one is just text.
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.failed {
+      return None;
+    }
+    let mut chunk = std::mem::take(&mut self.pending);
+    let mut games = 0;
+    let mut in_movetext = false;
+    let mut in_comment = false;
+    let mut line = Vec::new();
+    loop {
+      line.clear();
+      match self.reader.read_until(b'\n', &mut line) {
+        Ok(0) => break,
+        Ok(_) => (),
+        Err(e) => {
+          self.failed = true;
+          return Some(Err(e));
+        }
+      }
+      let indent = line.iter().take_while(|b| b.is_ascii_whitespace()).count();
+      let trimmed = &line[indent..];
+      if !in_comment && trimmed.starts_with(b"[") {
+        if in_movetext {
+          in_movetext = false;
+          games += 1;
+          if games == self.games_per_chunk {
+            self.pending = line;
+            return Some(Ok(chunk));
+          }
+        }
+      } else if !trimmed.is_empty() {
+        in_movetext = true;
+        for b in trimmed {
+          match b {
+            b'{' => in_comment = true,
+            b'}' => in_comment = false,
+            // The rest of the line is a comment.
+            b';' if !in_comment => break,
+            _ => (),
+          }
+        }
+      }
+      chunk.extend_from_slice(&line);
+    }
+    if chunk.iter().all(u8::is_ascii_whitespace) {
       None
     } else {
-      Some(self.clone())
+      Some(Ok(chunk))
     }
   }
 }
 
-fn promotion_value(piece: Option<chess::Piece>) -> Option<i32> {
-  piece.map(|p| match p {
-    chess::Piece::Bishop => 3,
-    chess::Piece::Knight => 3,
-    chess::Piece::Rook => 5,
-    chess::Piece::Queen => 9,
-    p => unreachable!("cannot promote to piece: {}", p),
-  })
+// Variant name for games set up from a position other than the usual one
+const FROM_POSITION: &str = "from_position";
+
+// Turns a Variant header into the name stored for it, e.g. "King of the Hill"
+// into king_of_the_hill.
+fn variant_name(header: &str) -> String {
+  header
+    .to_lowercase()
+    .split(|c: char| !c.is_ascii_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .join("_")
+}
+
+// Returns the argument of a `[%name ...]` command embedded in a comment.
+pub(crate) fn command<'a>(comment: &'a str, name: &str) -> Option<&'a str> {
+  let tag = format!("[%{} ", name);
+  let start = comment.find(&tag)? + tag.len();
+  let len = comment[start..].find(']')?;
+  Some(comment[start..start + len].trim())
 }
 
-fn dumbchess_square(s: chess::Square) -> dumbchess::Square {
-  match s {
-    chess::Square::A1 => dumbchess::Square::A1,
-    chess::Square::A2 => dumbchess::Square::A2,
-    chess::Square::A3 => dumbchess::Square::A3,
-    chess::Square::A4 => dumbchess::Square::A4,
-    chess::Square::A5 => dumbchess::Square::A5,
-    chess::Square::A6 => dumbchess::Square::A6,
-    chess::Square::A7 => dumbchess::Square::A7,
-    chess::Square::A8 => dumbchess::Square::A8,
-    chess::Square::B1 => dumbchess::Square::B1,
-    chess::Square::B2 => dumbchess::Square::B2,
-    chess::Square::B3 => dumbchess::Square::B3,
-    chess::Square::B4 => dumbchess::Square::B4,
-    chess::Square::B5 => dumbchess::Square::B5,
-    chess::Square::B6 => dumbchess::Square::B6,
-    chess::Square::B7 => dumbchess::Square::B7,
-    chess::Square::B8 => dumbchess::Square::B8,
-    chess::Square::C1 => dumbchess::Square::C1,
-    chess::Square::C2 => dumbchess::Square::C2,
-    chess::Square::C3 => dumbchess::Square::C3,
-    chess::Square::C4 => dumbchess::Square::C4,
-    chess::Square::C5 => dumbchess::Square::C5,
-    chess::Square::C6 => dumbchess::Square::C6,
-    chess::Square::C7 => dumbchess::Square::C7,
-    chess::Square::C8 => dumbchess::Square::C8,
-    chess::Square::D1 => dumbchess::Square::D1,
-    chess::Square::D2 => dumbchess::Square::D2,
-    chess::Square::D3 => dumbchess::Square::D3,
-    chess::Square::D4 => dumbchess::Square::D4,
-    chess::Square::D5 => dumbchess::Square::D5,
-    chess::Square::D6 => dumbchess::Square::D6,
-    chess::Square::D7 => dumbchess::Square::D7,
-    chess::Square::D8 => dumbchess::Square::D8,
-    chess::Square::E1 => dumbchess::Square::E1,
-    chess::Square::E2 => dumbchess::Square::E2,
-    chess::Square::E3 => dumbchess::Square::E3,
-    chess::Square::E4 => dumbchess::Square::E4,
-    chess::Square::E5 => dumbchess::Square::E5,
-    chess::Square::E6 => dumbchess::Square::E6,
-    chess::Square::E7 => dumbchess::Square::E7,
-    chess::Square::E8 => dumbchess::Square::E8,
-    chess::Square::F1 => dumbchess::Square::F1,
-    chess::Square::F2 => dumbchess::Square::F2,
-    chess::Square::F3 => dumbchess::Square::F3,
-    chess::Square::F4 => dumbchess::Square::F4,
-    chess::Square::F5 => dumbchess::Square::F5,
-    chess::Square::F6 => dumbchess::Square::F6,
-    chess::Square::F7 => dumbchess::Square::F7,
-    chess::Square::F8 => dumbchess::Square::F8,
-    chess::Square::G1 => dumbchess::Square::G1,
-    chess::Square::G2 => dumbchess::Square::G2,
-    chess::Square::G3 => dumbchess::Square::G3,
-    chess::Square::G4 => dumbchess::Square::G4,
-    chess::Square::G5 => dumbchess::Square::G5,
-    chess::Square::G6 => dumbchess::Square::G6,
-    chess::Square::G7 => dumbchess::Square::G7,
-    chess::Square::G8 => dumbchess::Square::G8,
-    chess::Square::H1 => dumbchess::Square::H1,
-    chess::Square::H2 => dumbchess::Square::H2,
-    chess::Square::H3 => dumbchess::Square::H3,
-    chess::Square::H4 => dumbchess::Square::H4,
-    chess::Square::H5 => dumbchess::Square::H5,
-    chess::Square::H6 => dumbchess::Square::H6,
-    chess::Square::H7 => dumbchess::Square::H7,
-    chess::Square::H8 => dumbchess::Square::H8,
-    unknown_square => unreachable!("unknown square: {}", unknown_square),
+// Parses an H:MM:SS clock value, with optional fractional seconds, into
+// milliseconds.
+fn parse_clock(value: &str) -> Option<i64> {
+  let mut seconds = 0.0;
+  for part in value.split(':') {
+    seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
   }
+  Some((seconds * 1000.0).round() as i64)
 }
@@ -0,0 +1,409 @@
+use std::collections::{HashMap, HashSet};
+
+use sqlx::Row;
+
+use crate::db::{self, Dialect};
+use crate::dumbchess;
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct Filter {
+  pub source: Option<String>,
+  // Unix timestamps; `since` is inclusive and `until` is exclusive.
+  pub since: Option<i64>,
+  pub until: Option<i64>,
+  pub color: Option<String>,
+  pub time_control: Option<String>,
+  // ECO code of the opening played
+  pub eco: Option<String>,
+  pub min_opponent_rating: Option<i32>,
+  pub max_opponent_rating: Option<i32>,
+  #[serde(default)]
+  pub scoring: Scoring,
+}
+
+// Rating at which a rating-weighted capture scores the captured piece's value.
+pub const RATING_BASELINE: i32 = 1500;
+
+// How captures turn into points.
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  Default,
+  PartialEq,
+  Eq,
+  serde::Serialize,
+  serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Scoring {
+  // The captured piece's value
+  #[default]
+  Flat,
+  // The captured piece's value scaled by the opponent's rating going into the
+  // game relative to RATING_BASELINE, so taking a 2000's queen is worth more
+  // than taking a 900's. Unrated opponents count as RATING_BASELINE.
+  RatingWeighted,
+}
+
+impl Scoring {
+  pub const NAMES: [&'static str; 2] = ["flat", "rating_weighted"];
+
+  pub fn name(self) -> &'static str {
+    Scoring::NAMES[self as usize]
+  }
+
+  pub fn from_name(name: &str) -> Option<Scoring> {
+    match name {
+      "flat" => Some(Scoring::Flat),
+      "rating_weighted" => Some(Scoring::RatingWeighted),
+      _ => None,
+    }
+  }
+
+  pub fn points(self, capture_score: i32, opponent_rating: i32) -> i64 {
+    match self {
+      Scoring::Flat => capture_score as i64,
+      Scoring::RatingWeighted => {
+        let rating =
+          if opponent_rating > 0 { opponent_rating } else { RATING_BASELINE };
+        (capture_score as f64 * rating as f64 / RATING_BASELINE as f64).round()
+          as i64
+      }
+    }
+  }
+}
+
+impl Filter {
+  fn matches_side(&self, color: &str, opponent_rating: i32) -> bool {
+    self.color.as_ref().map(|c| c == color).unwrap_or(true)
+      && self.min_opponent_rating.map(|r| opponent_rating >= r).unwrap_or(true)
+      && self.max_opponent_rating.map(|r| opponent_rating <= r).unwrap_or(true)
+  }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct PieceStats {
+  pub piece: String,
+  pub games: i64,
+  pub captures: i64,
+  pub points: i64,
+  pub times_captured: i64,
+  // Sum over all games of the ply on which the piece was captured, or the
+  // game length if it survived.
+  pub survival_plies: i64,
+  pub promotions: i64,
+  pub checkmates: i64,
+  // Games the piece played that its side won
+  pub wins: i64,
+}
+
+impl PieceStats {
+  pub fn average_survival_ply(&self) -> f64 {
+    if self.games == 0 {
+      return 0.0;
+    }
+    self.survival_plies as f64 / self.games as f64
+  }
+
+  pub fn promotion_rate(&self) -> f64 {
+    if self.games == 0 {
+      return 0.0;
+    }
+    self.promotions as f64 / self.games as f64
+  }
+}
+
+// Computes career statistics for each of a player's pieces across all stored
+// games matching the filter.
+pub async fn piece_stats(
+  db: &sqlx::Pool<sqlx::Any>,
+  player_id: &str,
+  filter: &Filter,
+) -> sqlx::Result<Vec<PieceStats>> {
+  let mut sql = String::from(
+    "SELECT g.id AS game_id, g.white_player_id, g.white_player_rating,
+      g.black_player_rating, g.starting_fen, g.result, m.move_num, m.color,
+      m.moved_piece, m.captured_piece, m.capture_score, m.promotion_value,
+      m.checkmate
+      FROM Games g JOIN Moves m ON m.game_id = g.id WHERE 1 = 1",
+  );
+  let values = db::GameFilter {
+    source: filter.source.clone(),
+    player_id: Some(player_id.to_owned()),
+    since: filter.since,
+    until: filter.until,
+    time_control: filter.time_control.clone(),
+    eco: filter.eco.clone(),
+    ..Default::default()
+  }
+  .push_conditions(&mut sql);
+  sql.push_str(" ORDER BY g.id, m.move_num");
+
+  let sql = Dialect::of(db).placeholders(&sql);
+  let rows = db::bind_all(sqlx::query(&sql), values).fetch_all(db).await?;
+
+  let mut stats: HashMap<String, PieceStats> = dumbchess::PIECES
+    .iter()
+    .map(|p| {
+      (p.to_string(), PieceStats { piece: p.to_string(), ..Default::default() })
+    })
+    .collect();
+
+  let mut game = GameTally::default();
+  for row in rows {
+    let game_id: String = row.try_get("game_id")?;
+    if game_id != game.game_id {
+      game.finish(&mut stats);
+      let white_player_id: String = row.try_get("white_player_id")?;
+      let (color, opponent_rating) = if white_player_id == player_id {
+        ("white", row.try_get("black_player_rating")?)
+      } else {
+        ("black", row.try_get("white_player_rating")?)
+      };
+      let starting_fen: Option<String> = row.try_get("starting_fen")?;
+      let result: String = row.try_get("result")?;
+      game = GameTally {
+        game_id,
+        color,
+        opponent_rating,
+        won: result
+          == if color == "white" { db::WHITE_WON } else { db::BLACK_WON },
+        included: filter.matches_side(color, opponent_rating),
+        spawned: starting_fen.and_then(|fen| spawned_pieces(&fen, color)),
+        ..Default::default()
+      };
+    }
+    if !game.included {
+      continue;
+    }
+
+    let move_num: i32 = row.try_get("move_num")?;
+    let color: String = row.try_get("color")?;
+    let captured_piece: String = row.try_get("captured_piece")?;
+    game.plies = move_num as i64 + 1;
+    if color == game.color {
+      let moved_piece: String = row.try_get("moved_piece")?;
+      if let Some(s) = stats.get_mut(&moved_piece) {
+        if !captured_piece.is_empty() {
+          s.captures += 1;
+          s.points += filter
+            .scoring
+            .points(row.try_get("capture_score")?, game.opponent_rating);
+        }
+        if row.try_get::<bool, _>("checkmate")? {
+          s.checkmates += 1;
+        }
+      }
+      if row.try_get::<i32, _>("promotion_value")? != 0 {
+        game.promoted.insert(moved_piece);
+      }
+    } else if !captured_piece.is_empty() {
+      game.captured.push((captured_piece, move_num as i64 + 1));
+    }
+  }
+  game.finish(&mut stats);
+
+  Ok(dumbchess::PIECES.iter().filter_map(|p| stats.remove(*p)).collect())
+}
+
+// A player's results, counting every stored game, including those stored
+// without moves (e.g. unsupported variants), which piece and opening stats
+// can't see.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct GamesPlayed {
+  pub games: i64,
+  pub wins: i64,
+  pub draws: i64,
+  pub losses: i64,
+}
+
+pub async fn games_played(
+  db: &sqlx::Pool<sqlx::Any>,
+  player_id: &str,
+  filter: &Filter,
+) -> sqlx::Result<GamesPlayed> {
+  let mut sql = String::from(
+    "SELECT g.white_player_id, g.white_player_rating, g.black_player_rating,
+      g.result FROM Games g WHERE 1 = 1",
+  );
+  let values = db::GameFilter {
+    source: filter.source.clone(),
+    player_id: Some(player_id.to_owned()),
+    since: filter.since,
+    until: filter.until,
+    time_control: filter.time_control.clone(),
+    eco: filter.eco.clone(),
+    ..Default::default()
+  }
+  .push_conditions(&mut sql);
+
+  let sql = Dialect::of(db).placeholders(&sql);
+  let rows = db::bind_all(sqlx::query(&sql), values).fetch_all(db).await?;
+
+  let mut played = GamesPlayed::default();
+  for row in rows {
+    let white_player_id: String = row.try_get("white_player_id")?;
+    let (color, opponent_rating) = if white_player_id == player_id {
+      ("white", row.try_get("black_player_rating")?)
+    } else {
+      ("black", row.try_get("white_player_rating")?)
+    };
+    if !filter.matches_side(color, opponent_rating) {
+      continue;
+    }
+    played.games += 1;
+    let result: String = row.try_get("result")?;
+    match (result.as_str(), color) {
+      (db::WHITE_WON, "white") | (db::BLACK_WON, "black") => played.wins += 1,
+      (db::WHITE_WON, _) | (db::BLACK_WON, _) => played.losses += 1,
+      (db::DRAWN, _) => played.draws += 1,
+      _ => (),
+    }
+  }
+  Ok(played)
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct OpeningStats {
+  pub eco: String,
+  pub opening: String,
+  pub games: i64,
+  pub captures: i64,
+  pub points: i64,
+  // Pieces the player lost to captures
+  pub pieces_lost: i64,
+}
+
+// Totals a player's captures in each opening they've played, most played
+// first.
+pub async fn opening_stats(
+  db: &sqlx::Pool<sqlx::Any>,
+  player_id: &str,
+  filter: &Filter,
+) -> sqlx::Result<Vec<OpeningStats>> {
+  let mut sql = String::from(
+    "SELECT g.id AS game_id, g.eco, g.opening, g.white_player_id,
+      g.white_player_rating, g.black_player_rating, m.color,
+      m.captured_piece, m.capture_score
+      FROM Games g JOIN Moves m ON m.game_id = g.id WHERE 1 = 1",
+  );
+  let values = db::GameFilter {
+    source: filter.source.clone(),
+    player_id: Some(player_id.to_owned()),
+    since: filter.since,
+    until: filter.until,
+    time_control: filter.time_control.clone(),
+    eco: filter.eco.clone(),
+    ..Default::default()
+  }
+  .push_conditions(&mut sql);
+  sql.push_str(" ORDER BY g.id, m.move_num");
+
+  let sql = Dialect::of(db).placeholders(&sql);
+  let rows = db::bind_all(sqlx::query(&sql), values).fetch_all(db).await?;
+
+  let mut stats: HashMap<(String, String), OpeningStats> = HashMap::new();
+  let mut game_id = String::new();
+  let mut player_color = "";
+  let mut opponent_rating = 0;
+  let mut included = false;
+  for row in rows {
+    let eco: String = row.try_get("eco")?;
+    let opening: String = row.try_get("opening")?;
+    let s = stats
+      .entry((eco.clone(), opening.clone()))
+      .or_insert_with(|| OpeningStats { eco, opening, ..Default::default() });
+    let row_game_id: String = row.try_get("game_id")?;
+    if row_game_id != game_id {
+      let white_player_id: String = row.try_get("white_player_id")?;
+      opponent_rating = if white_player_id == player_id {
+        player_color = "white";
+        row.try_get("black_player_rating")?
+      } else {
+        player_color = "black";
+        row.try_get("white_player_rating")?
+      };
+      included = filter.matches_side(player_color, opponent_rating);
+      if included {
+        s.games += 1;
+      }
+      game_id = row_game_id;
+    }
+    if !included {
+      continue;
+    }
+    let color: String = row.try_get("color")?;
+    let captured_piece: String = row.try_get("captured_piece")?;
+    if captured_piece.is_empty() {
+      continue;
+    }
+    if color == player_color {
+      s.captures += 1;
+      s.points +=
+        filter.scoring.points(row.try_get("capture_score")?, opponent_rating);
+    } else {
+      s.pieces_lost += 1;
+    }
+  }
+
+  let mut stats: Vec<_> = stats.into_values().filter(|s| s.games > 0).collect();
+  stats.sort_by(|a, b| b.games.cmp(&a.games).then(a.eco.cmp(&b.eco)));
+  Ok(stats)
+}
+
+// Per-game bookkeeping for piece_stats; folded into the career totals once all
+// of a game's moves have been seen.
+#[derive(Default)]
+struct GameTally {
+  game_id: String,
+  color: &'static str,
+  opponent_rating: i32,
+  won: bool,
+  included: bool,
+  plies: i64,
+  promoted: HashSet<String>,
+  // Pieces captured by the opponent, with the ply on which it happened.
+  captured: Vec<(String, i64)>,
+  // The player's pieces on the board at the start, if not all of them were,
+  // as in odds games. Pieces that never spawned didn't play the game.
+  spawned: Option<HashSet<String>>,
+}
+
+fn spawned_pieces(fen: &str, color: &str) -> Option<HashSet<String>> {
+  let board = dumbchess::Board::from_fen(fen).ok()?;
+  Some(
+    board
+      .lifecycles()
+      .into_iter()
+      .filter(|l| l.color == color)
+      .map(|l| l.piece)
+      .collect(),
+  )
+}
+
+impl GameTally {
+  fn finish(&self, stats: &mut HashMap<String, PieceStats>) {
+    if !self.included {
+      return;
+    }
+    for s in stats.values_mut() {
+      if matches!(&self.spawned, Some(spawned) if !spawned.contains(&s.piece)) {
+        continue;
+      }
+      s.games += 1;
+      if self.won {
+        s.wins += 1;
+      }
+      let capture_ply =
+        self.captured.iter().find(|(p, _)| p == &s.piece).map(|(_, ply)| *ply);
+      if capture_ply.is_some() {
+        s.times_captured += 1;
+      }
+      s.survival_plies += capture_ply.unwrap_or(self.plies);
+      if self.promoted.contains(&s.piece) {
+        s.promotions += 1;
+      }
+    }
+  }
+}
@@ -0,0 +1,244 @@
+use std::process::Stdio;
+
+use thiserror::Error as ThisError;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use crate::db::{self, Dialect};
+use crate::eval::{self, Eval};
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+  #[error("failed to talk to engine: {source}")]
+  Io {
+    #[from]
+    source: std::io::Error,
+  },
+  #[error("engine exited unexpectedly")]
+  EngineExited,
+  #[error("game not found: {0}")]
+  GameNotFound(String),
+  #[error("database error: {source}")]
+  Database {
+    #[from]
+    source: sqlx::Error,
+  },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// How long the engine should think about each position.
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+  Depth(u32),
+  Nodes(u64),
+}
+
+impl std::fmt::Display for Limit {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Limit::Depth(depth) => write!(f, "depth {}", depth),
+      Limit::Nodes(nodes) => write!(f, "nodes {}", nodes),
+    }
+  }
+}
+
+// A running UCI engine process.
+pub struct Engine {
+  child: Child,
+  stdin: ChildStdin,
+  stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Engine {
+  // Starts the engine binary at `path` (looked up on PATH if it isn't one)
+  // and waits for it to finish initializing.
+  pub async fn start(path: &str) -> Result<Engine> {
+    let mut child = Command::new(path)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .kill_on_drop(true)
+      .spawn()?;
+    let stdin = child.stdin.take().ok_or(Error::EngineExited)?;
+    let stdout = child.stdout.take().ok_or(Error::EngineExited)?;
+    let mut engine =
+      Engine { child, stdin, stdout: BufReader::new(stdout).lines() };
+    engine.send("uci").await?;
+    engine.read_until("uciok").await?;
+    engine.ready().await?;
+    Ok(engine)
+  }
+
+  // Writing to an engine that has already exited fails with a broken pipe,
+  // which is reported the same as the engine closing its output.
+  async fn send(&mut self, command: &str) -> Result<()> {
+    let line = format!("{}\n", command);
+    let written = match self.stdin.write_all(line.as_bytes()).await {
+      Ok(()) => self.stdin.flush().await,
+      Err(e) => Err(e),
+    };
+    match written {
+      Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+        Err(Error::EngineExited)
+      }
+      written => Ok(written?),
+    }
+  }
+
+  // Reads output up to and including the first line starting with `prefix`,
+  // returning every line read.
+  async fn read_until(&mut self, prefix: &str) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    loop {
+      let line = self.stdout.next_line().await?.ok_or(Error::EngineExited)?;
+      let done = line.starts_with(prefix);
+      lines.push(line);
+      if done {
+        return Ok(lines);
+      }
+    }
+  }
+
+  async fn ready(&mut self) -> Result<()> {
+    self.send("isready").await?;
+    self.read_until("readyok").await?;
+    Ok(())
+  }
+
+  pub async fn new_game(&mut self) -> Result<()> {
+    self.send("ucinewgame").await?;
+    self.ready().await
+  }
+
+  // Evaluates the position reached by playing `moves` (in UCI notation) from
+  // `starting_fen`, or from the usual starting position when that's None. The
+  // score is from the point of view of the side to move, as UCI reports it.
+  // Returns None for finished games, where there is nothing left to search.
+  pub async fn evaluate(
+    &mut self,
+    starting_fen: Option<&str>,
+    moves: &[String],
+    limit: Limit,
+  ) -> Result<Option<Eval>> {
+    let mut position = match starting_fen {
+      Some(fen) => format!("position fen {}", fen),
+      None => String::from("position startpos"),
+    };
+    if !moves.is_empty() {
+      position.push_str(&format!(" moves {}", moves.join(" ")));
+    }
+    self.send(&position).await?;
+    self.send(&format!("go {}", limit)).await?;
+    let output = self.read_until("bestmove").await?;
+    Ok(
+      output
+        .iter()
+        .rev()
+        .find_map(|line| parse_score(line))
+        .filter(|eval| *eval != Eval::Mate(0)),
+    )
+  }
+
+  pub async fn quit(mut self) -> Result<()> {
+    self.send("quit").await?;
+    self.child.wait().await?;
+    Ok(())
+  }
+}
+
+// Pulls the score out of an `info ... score cp 31 ...` or
+// `info ... score mate -4 ...` line. Bounds from aspiration windows are
+// skipped, since they aren't the engine's actual evaluation.
+fn parse_score(line: &str) -> Option<Eval> {
+  let mut tokens = line.split_whitespace();
+  if tokens.next() != Some("info") {
+    return None;
+  }
+  let mut tokens = tokens.skip_while(|t| *t != "score").skip(1);
+  let eval = match (tokens.next()?, tokens.next()?.parse().ok()?) {
+    ("cp", cp) => Eval::Centipawns(cp),
+    ("mate", n) => Eval::Mate(n),
+    _ => return None,
+  };
+  match tokens.next() {
+    Some("lowerbound") | Some("upperbound") => None,
+    _ => Some(eval),
+  }
+}
+
+// Flips an evaluation from the side to move's point of view to white's.
+fn white_point_of_view(eval: Eval, white_to_move: bool) -> Eval {
+  match (eval, white_to_move) {
+    (eval, true) => eval,
+    (Eval::Centipawns(cp), false) => Eval::Centipawns(-cp),
+    (Eval::Mate(n), false) => Eval::Mate(-n),
+  }
+}
+
+// Evaluates every position in a stored game, then writes the evals, centipawn
+// losses, classifications and per-side accuracy back to the database.
+pub async fn analyze_game(
+  db: &sqlx::Pool<sqlx::Any>,
+  engine: &mut Engine,
+  game_id: &str,
+  limit: Limit,
+) -> Result<db::Game> {
+  let mut game = db::game(db, game_id)
+    .await?
+    .ok_or_else(|| Error::GameNotFound(game_id.to_owned()))?;
+  let mut moves = db::moves_for_game(db, game_id).await?;
+
+  engine.new_game().await?;
+  let mut played = Vec::new();
+  for m in moves.iter_mut() {
+    played.push(m.uci_move());
+    let white_to_move = m.color != "white";
+    if let Some(eval) =
+      engine.evaluate(game.starting_fen.as_deref(), &played, limit).await?
+    {
+      m.set_eval(white_point_of_view(eval, white_to_move));
+    }
+  }
+  eval::annotate(&mut moves);
+  game.white_accuracy = eval::accuracy(&moves, "white");
+  game.black_accuracy = eval::accuracy(&moves, "black");
+
+  let dialect = Dialect::of(db);
+  let mut tx = db.begin().await?;
+  for m in moves.iter() {
+    m.update_eval_query(dialect, game_id).execute(&mut tx).await?;
+  }
+  game.update_accuracy_query(dialect).execute(&mut tx).await?;
+  tx.commit().await?;
+  Ok(game)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_scores() {
+    assert_eq!(
+      parse_score("info depth 20 seldepth 28 score cp 31 nodes 1 pv e2e4"),
+      Some(Eval::Centipawns(31))
+    );
+    assert_eq!(
+      parse_score("info depth 30 score mate -4 pv h7h8"),
+      Some(Eval::Mate(-4))
+    );
+    assert_eq!(parse_score("info depth 12 score cp 40 lowerbound"), None);
+    assert_eq!(parse_score("info depth 12 score cp -7 upperbound"), None);
+    assert_eq!(parse_score("info string score cp"), None);
+    assert_eq!(parse_score("info depth 1 nodes 20"), None);
+    assert_eq!(parse_score("bestmove e2e4 ponder e7e5"), None);
+  }
+
+  #[test]
+  fn flips_to_white_point_of_view() {
+    let cp = Eval::Centipawns(25);
+    assert_eq!(white_point_of_view(cp, true), cp);
+    assert_eq!(white_point_of_view(cp, false), Eval::Centipawns(-25));
+    assert_eq!(white_point_of_view(Eval::Mate(3), false), Eval::Mate(-3));
+  }
+}
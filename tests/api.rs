@@ -0,0 +1,249 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use fantasy_chess::db::{self, Dialect};
+use fantasy_chess::{api, league};
+use serde_json::Value;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use common::{capture, game, insert};
+
+// Three games: alice beats bob, alice beats bob again with bob's bishop
+// scoring, and an unrelated chess.com game. Team A holds alice's g knight and
+// team B holds bob's f bishop.
+async fn seed() -> (Arc<sqlx::Pool<sqlx::Any>>, String) {
+  let db = common::sqlite_db().await;
+
+  let mut g1 = game("g1", "alice", "bob", 1000);
+  g1.result = db::WHITE_WON.to_owned();
+  g1.eco = "C20".to_owned();
+  g1.opening = "King's Pawn Game".to_owned();
+  insert(
+    &db,
+    db::GameRecord {
+      game: g1,
+      moves: vec![
+        capture(1, "white", "knight g", ("g1", "f3"), "", 0),
+        capture(3, "white", "knight g", ("f3", "e5"), "pawn e", 1),
+      ],
+      lifecycles: vec![db::PieceLifecycle {
+        color: "white".to_owned(),
+        piece: "knight g".to_owned(),
+        spawn_square: "g1".to_owned(),
+        squares_visited: "g1 f3 e5".to_owned(),
+        move_count: 2,
+        captured_ply: None,
+        captured_by: String::new(),
+      }],
+    },
+  )
+  .await;
+
+  let mut g2 = game("g2", "bob", "alice", 2000);
+  g2.result = db::BLACK_WON.to_owned();
+  insert(
+    &db,
+    db::GameRecord {
+      game: g2,
+      moves: vec![capture(5, "white", "bishop f", ("c4", "f7"), "pawn f", 3)],
+      lifecycles: Vec::new(),
+    },
+  )
+  .await;
+
+  let mut g3 = game("g3", "carol", "dave", 3000);
+  g3.source = "chess_com".to_owned();
+  insert(
+    &db,
+    db::GameRecord { game: g3, moves: Vec::new(), lifecycles: Vec::new() },
+  )
+  .await;
+
+  let dialect = Dialect::of(&db);
+  let mut league = league::League::new(
+    "Test",
+    league::WaiverMode::Priority,
+    1,
+    Default::default(),
+  );
+  let a = league.add_team("A", 100).id.clone();
+  let b = league.add_team("B", 100).id.clone();
+  let mut queries = vec![league.insert_query(dialect)];
+  queries.extend(league.teams.iter().map(|t| t.upsert_query(dialect)));
+  for (team_id, player_id, piece) in
+    [(&a, "alice", "knight g"), (&b, "bob", "bishop f")].iter()
+  {
+    let piece = league::PieceId {
+      source: "lichess".to_owned(),
+      player_id: player_id.to_string(),
+      piece: piece.to_string(),
+    };
+    for t in league.add_free_agent(team_id, piece, None, 0).unwrap() {
+      queries.push(t.insert_query(dialect));
+    }
+  }
+  for query in queries {
+    query.execute(&db).await.unwrap();
+  }
+  (Arc::new(db), league.id)
+}
+
+async fn get(
+  db: &Arc<sqlx::Pool<sqlx::Any>>,
+  uri: &str,
+) -> (StatusCode, Value) {
+  let response = api::router(db.clone())
+    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+    .await
+    .unwrap();
+  let status = response.status();
+  let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+  (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn ids(items: &Value, key: &str) -> Vec<String> {
+  items
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|i| i[key].as_str().unwrap().to_owned())
+    .collect()
+}
+
+#[tokio::test]
+async fn games() {
+  let (db, _) = seed().await;
+
+  let (status, page) = get(&db, "/games").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(ids(&page["items"], "id"), ["g3", "g2", "g1"]);
+  assert_eq!(page["limit"], 100);
+  assert_eq!(page["offset"], 0);
+
+  let (_, page) = get(&db, "/games?limit=1&offset=1").await;
+  assert_eq!(ids(&page["items"], "id"), ["g2"]);
+  assert_eq!(page["limit"], 1);
+  assert_eq!(page["offset"], 1);
+
+  let (_, page) = get(&db, "/games?player_id=alice").await;
+  assert_eq!(ids(&page["items"], "id"), ["g2", "g1"]);
+  let (_, page) = get(&db, "/games?source=chess_com").await;
+  assert_eq!(ids(&page["items"], "id"), ["g3"]);
+  let (_, page) = get(&db, "/games?since=1000&until=2000").await;
+  assert_eq!(ids(&page["items"], "id"), ["g1"]);
+  let (_, page) = get(&db, "/games?eco=C20").await;
+  assert_eq!(ids(&page["items"], "id"), ["g1"]);
+
+  let (status, game) = get(&db, "/games/g1").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(game["white_player_id"], "alice");
+  assert_eq!(game["result"], "1-0");
+  let (status, body) = get(&db, "/games/missing").await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert_eq!(body["error"], "not found");
+
+  let (_, moves) = get(&db, "/games/g1/moves").await;
+  assert_eq!(ids(&moves, "ending_location"), ["f3", "e5"]);
+  assert_eq!(moves[1]["captured_piece"], "pawn e");
+  let (_, pieces) = get(&db, "/games/g1/pieces").await;
+  assert_eq!(ids(&pieces, "piece"), ["knight g"]);
+  assert_eq!(pieces[0]["squares_visited"], "g1 f3 e5");
+}
+
+#[tokio::test]
+async fn players() {
+  let (db, _) = seed().await;
+
+  let (status, page) = get(&db, "/players").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(ids(&page["items"], "id"), ["carol", "dave", "alice", "bob"]);
+  let (_, page) = get(&db, "/players?limit=2&offset=2").await;
+  assert_eq!(ids(&page["items"], "id"), ["alice", "bob"]);
+  assert_eq!(page["limit"], 2);
+
+  let (_, pieces) = get(&db, "/players/alice/pieces").await;
+  let knight =
+    pieces.as_array().unwrap().iter().find(|p| p["piece"] == "knight g");
+  let knight = knight.unwrap();
+  assert_eq!(knight["captures"], 1);
+  assert_eq!(knight["points"], 1);
+  // Wins count every game the piece's side won, captures or not.
+  assert_eq!(knight["wins"], 2);
+  let (_, pieces) = get(&db, "/players/alice/pieces?since=1500").await;
+  let knight =
+    pieces.as_array().unwrap().iter().find(|p| p["piece"] == "knight g");
+  assert_eq!(knight.unwrap()["captures"], 0);
+
+  let (_, openings) = get(&db, "/players/alice/openings").await;
+  let c20 = openings.as_array().unwrap().iter().find(|o| o["eco"] == "C20");
+  assert_eq!(c20.unwrap()["points"], 1);
+
+  // Games stored without moves still count as played.
+  let mut g4 = game("g4", "alice", "bob", 4000);
+  g4.variant = db::CHESS960_VARIANT.to_owned();
+  g4.result = db::DRAWN.to_owned();
+  insert(
+    &db,
+    db::GameRecord { game: g4, moves: Vec::new(), lifecycles: Vec::new() },
+  )
+  .await;
+  let (_, played) = get(&db, "/players/alice/games_played").await;
+  assert_eq!(played["games"], 3);
+  assert_eq!(played["wins"], 2);
+  assert_eq!(played["draws"], 1);
+  let (_, played) = get(&db, "/players/bob/games_played?color=white").await;
+  assert_eq!(played["games"], 1);
+  assert_eq!(played["losses"], 1);
+}
+
+#[tokio::test]
+async fn leagues() {
+  let (db, league_id) = seed().await;
+
+  let (status, page) = get(&db, "/leagues").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(ids(&page["items"], "id"), [league_id.as_str()]);
+  let (_, page) = get(&db, "/leagues?offset=1").await;
+  assert!(page["items"].as_array().unwrap().is_empty());
+
+  let (_, league) = get(&db, &format!("/leagues/{}", league_id)).await;
+  assert_eq!(ids(&league["teams"], "name"), ["A", "B"]);
+  assert_eq!(league["transactions"].as_array().unwrap().len(), 2);
+  let (status, _) = get(&db, "/leagues/missing").await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+
+  let (_, rosters) = get(&db, &format!("/leagues/{}/rosters", league_id)).await;
+  assert_eq!(rosters[0]["team"]["name"], "A");
+  assert_eq!(rosters[0]["pieces"][0]["piece"], "knight g");
+  assert_eq!(rosters[1]["pieces"][0]["piece"], "bishop f");
+  let (_, rosters) =
+    get(&db, &format!("/leagues/{}/rosters?time=-1", league_id)).await;
+  assert!(rosters[0]["pieces"].as_array().unwrap().is_empty());
+
+  // A: one capture plus one win; B: one bishop capture in a lost game.
+  let (_, standings) =
+    get(&db, &format!("/leagues/{}/standings", league_id)).await;
+  let names: Vec<&Value> =
+    standings.as_array().unwrap().iter().map(|s| &s["team"]["name"]).collect();
+  assert_eq!(names, ["B", "A"]);
+  assert_eq!(standings[0]["score"], 3);
+  assert_eq!(standings[1]["score"], 2);
+
+  let (_, matchups) =
+    get(&db, &format!("/leagues/{}/matchups", league_id)).await;
+  assert_eq!(matchups.as_array().unwrap().len(), 1);
+  assert_eq!(matchups[0]["home"]["name"], "A");
+  assert_eq!(matchups[0]["away"]["name"], "B");
+  assert_eq!(matchups[0]["home_score"], 2);
+  assert_eq!(matchups[0]["away_score"], 3);
+  let (_, matchups) =
+    get(&db, &format!("/leagues/{}/matchups?start=1500", league_id)).await;
+  assert_eq!(matchups[0]["home_score"], 0);
+  assert_eq!(matchups[0]["away_score"], 3);
+  let (_, matchups) =
+    get(&db, &format!("/leagues/{}/matchups?end=1500", league_id)).await;
+  assert_eq!(matchups[0]["home_score"], 2);
+  assert_eq!(matchups[0]["away_score"], 0);
+}
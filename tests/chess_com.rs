@@ -0,0 +1,41 @@
+use fantasy_chess::chess_com::GameResponse;
+use fantasy_chess::db;
+use fantasy_chess::pgn::VariantPolicy;
+
+const GAME_JSON: &str = include_str!("../src/testdata/game_response.json");
+
+fn chess960() -> GameResponse {
+  let mut response: serde_json::Value =
+    serde_json::from_str(GAME_JSON).unwrap();
+  response["game"]["isChess960"] = true.into();
+  serde_json::from_value(response).unwrap()
+}
+
+#[test]
+fn chess960_variant() {
+  match chess960().into_record(VariantPolicy::Skip) {
+    Err(db::Rejection::Nonstandard(kind)) => assert_eq!(kind, "chess960"),
+    other => panic!("expected a nonstandard game, got {:?}", other.err()),
+  }
+
+  let record = chess960().into_record(VariantPolicy::Metadata).unwrap();
+  assert_eq!(record.game.variant, db::CHESS960_VARIANT);
+  assert_eq!(record.game.result, db::WHITE_WON);
+  assert!(record.moves.is_empty());
+  assert!(record.lifecycles.is_empty());
+}
+
+#[test]
+fn optional_fields() {
+  let mut response: serde_json::Value =
+    serde_json::from_str(GAME_JSON).unwrap();
+  for field in ["isCheckmate", "baseTime1", "timeIncrement1"].iter() {
+    response["game"].as_object_mut().unwrap().remove(*field);
+  }
+  let response: GameResponse = serde_json::from_value(response).unwrap();
+  let record = response.into_record(VariantPolicy::Skip).unwrap();
+  assert_eq!(record.game.time_control, "-");
+  assert_eq!(record.game.time_class(), Some(db::TimeClass::Correspondence));
+  assert!(!record.moves.is_empty());
+  assert!(!record.moves.last().unwrap().checkmate);
+}
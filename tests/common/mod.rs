@@ -0,0 +1,70 @@
+// Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use fantasy_chess::db::{self, Dialect};
+
+// Migrates a fresh SQLite database in the temp directory.
+pub async fn sqlite_db() -> sqlx::Pool<sqlx::Any> {
+  let path = std::env::temp_dir()
+    .join(format!("fantasy_chess_{}.db", uuid::Uuid::new_v4()));
+  let db = sqlx::any::AnyPoolOptions::new()
+    .max_connections(1)
+    .connect(&format!("sqlite://{}?mode=rwc", path.display()))
+    .await
+    .unwrap();
+  db::migrate(&db).await.unwrap();
+  db
+}
+
+pub fn game(id: &str, white: &str, black: &str, end_time: i64) -> db::Game {
+  db::Game {
+    id: id.to_owned(),
+    source: "lichess".to_owned(),
+    source_id: id.to_owned(),
+    end_time,
+    time_control: "300+0".to_owned(),
+    white_player_id: white.to_owned(),
+    white_player_name: white.to_owned(),
+    white_player_rating: 1500,
+    black_player_id: black.to_owned(),
+    black_player_name: black.to_owned(),
+    black_player_rating: 1600,
+    ..db::Game::empty()
+  }
+}
+
+pub fn capture(
+  move_num: i32,
+  color: &str,
+  moved_piece: &str,
+  (from, to): (&str, &str),
+  captured_piece: &str,
+  capture_score: i32,
+) -> db::Move {
+  db::Move {
+    move_num,
+    color: color.to_owned(),
+    moved_piece: moved_piece.to_owned(),
+    starting_location: from.to_owned(),
+    ending_location: to.to_owned(),
+    captured_piece: captured_piece.to_owned(),
+    capture_score,
+    promotion_value: 0,
+    checkmate: false,
+    san: String::new(),
+    uci: format!("{}{}", from, to),
+    fen: None,
+    clock_ms: None,
+    elapsed_ms: None,
+    eval_cp: None,
+    eval_mate: None,
+    cp_loss: None,
+    classification: None,
+  }
+}
+
+pub async fn insert(db: &sqlx::Pool<sqlx::Any>, record: db::GameRecord) {
+  for query in record.insert_queries(Dialect::of(db)) {
+    query.execute(db).await.unwrap();
+  }
+}
@@ -0,0 +1,248 @@
+// Replays the testdata games on dumbchess alone and on the two boards ingest
+// used before it, where minorhacks_chess resolved SAN and wrote FEN while
+// dumbchess tracked pieces, and checks that both produce the same moves,
+// positions and lifecycles.
+use std::str::FromStr;
+
+use fantasy_chess::chess_com::GameResponse;
+use fantasy_chess::db;
+use fantasy_chess::dumbchess::{Board, Ending, Role, Square};
+use fantasy_chess::pgn::{GameScore, VariantPolicy};
+use minorhacks_chess as chess;
+
+// Games covering pins, en passant, promotion, castling and disambiguation.
+const GAMES_PGN: &str = include_str!("../src/testdata/games.pgn");
+const GAME_JSON: &str = include_str!("../src/testdata/game_response.json");
+
+// The moves of a game as the old two-board replay recorded them, along with
+// the lifecycles its dumbchess board ended up with.
+struct Replay {
+  moves: Vec<db::Move>,
+  lifecycles: Vec<db::PieceLifecycle>,
+}
+
+fn square(square: chess::Square) -> Square {
+  Square::parse(square.to_string().as_bytes()).unwrap()
+}
+
+fn role(piece: chess::Piece) -> Role {
+  match piece {
+    chess::Piece::Knight => Role::Knight,
+    chess::Piece::Bishop => Role::Bishop,
+    chess::Piece::Rook => Role::Rook,
+    _ => Role::Queen,
+  }
+}
+
+fn chess_piece(role: char) -> chess::Piece {
+  match role {
+    'n' => chess::Piece::Knight,
+    'b' => chess::Piece::Bishop,
+    'r' => chess::Piece::Rook,
+    _ => chess::Piece::Queen,
+  }
+}
+
+// Plays a move on both boards the way ingest used to. minorhacks_chess always
+// wrote "0 1" for the FEN counters, so they're recomputed here to compare
+// against the real counters dumbchess now writes.
+fn play_old(
+  position: &mut chess::Board,
+  board: &mut Board,
+  halfmove_clock: &mut u32,
+  m: chess::ChessMove,
+) -> db::Move {
+  assert!(position.legal(m), "{} is illegal in {}", m, position);
+  let resets = position.piece_on(m.get_source()) == Some(chess::Piece::Pawn)
+    || position.piece_on(m.get_dest()).is_some();
+  let mut db_move = board
+    .make_move(
+      &square(m.get_source()),
+      &square(m.get_dest()),
+      m.get_promotion().map(role),
+    )
+    .unwrap();
+  *position = position.make_move_new(m);
+  *halfmove_clock = if resets { 0 } else { *halfmove_clock + 1 };
+
+  let fen = position.to_string();
+  let fields: Vec<&str> = fen.split_whitespace().take(4).collect();
+  db_move.uci = m.to_string();
+  db_move.fen = Some(format!(
+    "{} {} {}",
+    fields.join(" "),
+    halfmove_clock,
+    (db_move.move_num + 1) / 2 + 1,
+  ));
+  db_move
+}
+
+fn replay_san(sans: &[String]) -> Replay {
+  let mut position = chess::Board::default();
+  let mut board = Board::starting();
+  let mut halfmove_clock = 0;
+  let moves = sans
+    .iter()
+    .map(|san| {
+      let m = chess::ChessMove::from_san(&position, san)
+        .unwrap_or_else(|_| panic!("invalid SAN {} in {}", san, position));
+      let mut db_move =
+        play_old(&mut position, &mut board, &mut halfmove_clock, m);
+      db_move.san = san.clone();
+      db_move.checkmate = san.ends_with('#');
+      db_move
+    })
+    .collect();
+  Replay { moves, lifecycles: board.lifecycles() }
+}
+
+fn replay_squares(moves: &[db::Move]) -> Replay {
+  let mut position = chess::Board::default();
+  let mut board = Board::starting();
+  let mut halfmove_clock = 0;
+  let moves = moves
+    .iter()
+    .map(|m| {
+      let m = chess::ChessMove::new(
+        chess::Square::from_str(&m.starting_location).unwrap(),
+        chess::Square::from_str(&m.ending_location).unwrap(),
+        m.uci.chars().nth(4).map(chess_piece),
+      );
+      play_old(&mut position, &mut board, &mut halfmove_clock, m)
+    })
+    .collect();
+  Replay { moves, lifecycles: board.lifecycles() }
+}
+
+// The fields both replays fill in; clocks and evals come from the source.
+fn summary(m: &db::Move) -> serde_json::Value {
+  serde_json::json!([
+    m.move_num,
+    m.color,
+    m.moved_piece,
+    m.starting_location,
+    m.ending_location,
+    m.captured_piece,
+    m.capture_score,
+    m.promotion_value,
+    m.checkmate,
+    m.san,
+    m.uci,
+    m.fen,
+  ])
+}
+
+fn assert_same(name: &str, record: &db::GameRecord, old: &Replay) {
+  assert_eq!(record.moves.len(), old.moves.len(), "{}", name);
+  for (new, old) in record.moves.iter().zip(&old.moves) {
+    assert_eq!(summary(new), summary(old), "{} ply {}", name, new.move_num);
+  }
+  assert_eq!(
+    serde_json::to_value(&record.lifecycles).unwrap(),
+    serde_json::to_value(&old.lifecycles).unwrap(),
+    "{}",
+    name
+  );
+}
+
+#[test]
+fn pgn_matches_two_board_replay() {
+  let mut reader = pgn_reader::BufferedReader::new_cursor(GAMES_PGN.as_bytes());
+  let mut games = 0;
+  while let Some(record) = reader.read_game(&mut GameScore::new()).unwrap() {
+    let record =
+      record.unwrap_or_else(|rejection| panic!("rejected: {}", rejection));
+    let sans: Vec<String> =
+      record.moves.iter().map(|m| m.san.clone()).collect();
+    assert_same(&record.game.source_id, &record, &replay_san(&sans));
+    games += 1;
+  }
+  assert_eq!(games, 3);
+}
+
+#[test]
+fn chess_com_matches_two_board_replay() {
+  let response: GameResponse = serde_json::from_str(GAME_JSON).unwrap();
+  let record = response.into_record(VariantPolicy::Skip).unwrap();
+  let mut old = replay_squares(&record.moves);
+  // The move list has no SAN, so only the checkmate flag comes from the game.
+  if let (Some(new), Some(old)) = (record.moves.last(), old.moves.last_mut()) {
+    old.checkmate = new.checkmate;
+  }
+  assert_same("game_response.json", &record, &old);
+}
+
+#[test]
+fn san_matches_recorded() {
+  let mut reader = pgn_reader::BufferedReader::new_cursor(GAMES_PGN.as_bytes());
+  while let Some(record) = reader.read_game(&mut GameScore::new()).unwrap() {
+    let record = record.unwrap();
+    let mut board = Board::starting();
+    for m in &record.moves {
+      let start = Square::parse(m.starting_location.as_bytes()).unwrap();
+      let end = Square::parse(m.ending_location.as_bytes()).unwrap();
+      let promotion = m.uci.chars().nth(4).and_then(Role::from_letter);
+      assert_eq!(board.san(start, end, promotion).unwrap(), m.san);
+      board.make_move(&start, &end, promotion).unwrap();
+    }
+  }
+  let board = Board::starting();
+  assert!(board.san(Square::E2, Square::E5, None).is_err());
+  assert!(board.san(Square::E7, Square::E5, None).is_err());
+}
+
+#[test]
+fn fen_counters() {
+  let mut board = Board::starting();
+  let fens: Vec<String> = ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4"]
+    .iter()
+    .map(|san| board.make_san_move(san).unwrap().fen.unwrap())
+    .collect();
+  assert_eq!(
+    fens.last().unwrap(),
+    "r1bqkbnr/1ppp1ppp/p1n5/4p3/B3P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 1 4"
+  );
+  assert!(fens[0].ends_with(" b KQkq - 0 1"));
+  assert!(fens[3].ends_with(" w KQkq - 2 3"));
+}
+
+fn play(sans: &str) -> Board {
+  let mut board = Board::starting();
+  for san in sans.split_whitespace() {
+    board.make_san_move(san).unwrap();
+  }
+  board
+}
+
+#[test]
+fn endings() {
+  assert_eq!(Board::starting().ending(), None);
+  assert_eq!(play("f3 e5 g4 Qh4#").ending(), Some(Ending::Checkmate));
+  // Sam Loyd's ten-move stalemate
+  let stalemate = play(
+    "e3 a5 Qh5 Ra6 Qxa5 h5 h4 Rah6 Qxc7 f6 Qxd7+ Kf7 Qxb7 Qd3 Qxb8 Qh7 Qxc8 \
+     Kg6 Qe6",
+  );
+  assert_eq!(stalemate.ending(), Some(Ending::Stalemate));
+
+  let ending = |fen| Board::from_fen(fen).unwrap().ending();
+  for fen in [
+    "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+    "4k3/8/8/8/8/8/8/1N2K3 w - - 0 1",
+    // Bishops on squares of the same color
+    "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+  ]
+  .iter()
+  {
+    assert_eq!(ending(fen), Some(Ending::InsufficientMaterial), "{}", fen);
+  }
+  for fen in [
+    "2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+    "4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1",
+    "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+  ]
+  .iter()
+  {
+    assert_eq!(ending(fen), None, "{}", fen);
+  }
+}
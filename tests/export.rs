@@ -0,0 +1,196 @@
+mod common;
+
+use fantasy_chess::db;
+use fantasy_chess::dumbchess::Board;
+use fantasy_chess::eval::Eval;
+use fantasy_chess::export::{self, Format, Table};
+use fantasy_chess::pgn::GameScore;
+
+use common::{capture, game, insert};
+
+async fn export_string(
+  db: &sqlx::Pool<sqlx::Any>,
+  table: Table,
+  format: Format,
+  filter: &db::GameFilter,
+) -> (usize, String) {
+  let path = std::env::temp_dir()
+    .join(format!("fantasy_chess_{}.out", uuid::Uuid::new_v4()));
+  let out = std::fs::File::create(&path).unwrap();
+  let count = export::export(db, table, format, filter, out).await.unwrap();
+  let written = std::fs::read_to_string(&path).unwrap();
+  std::fs::remove_file(&path).unwrap();
+  (count, written)
+}
+
+#[tokio::test]
+async fn piece_stats() {
+  let db = common::sqlite_db().await;
+  let mut g1 = game("g1", "alice", "bob", 1000);
+  g1.result = db::WHITE_WON.to_owned();
+  insert(
+    &db,
+    db::GameRecord {
+      game: g1,
+      moves: vec![
+        capture(0, "white", "knight g", ("g1", "f3"), "", 0),
+        capture(1, "black", "pawn e", ("e7", "e5"), "", 0),
+        capture(2, "white", "knight g", ("f3", "e5"), "pawn e", 1),
+      ],
+      lifecycles: Vec::new(),
+    },
+  )
+  .await;
+
+  let filter = db::GameFilter {
+    player_id: Some("alice".to_owned()),
+    ..Default::default()
+  };
+  let (count, csv) =
+    export_string(&db, Table::PieceStats, Format::Csv, &filter).await;
+  let lines: Vec<&str> = csv.lines().collect();
+  assert_eq!(
+    lines[0],
+    "source,player_id,piece,games,captures,points,times_captured,\
+     survival_plies,promotions,checkmates,wins,average_survival_ply,\
+     promotion_rate"
+  );
+  assert_eq!(count, 16);
+  assert_eq!(lines.len(), 17);
+  assert!(lines.contains(&",alice,knight g,1,1,1,0,3,0,0,1,3,0"));
+
+  // Without a player, every account gets rows; bob's pawn was captured.
+  let (count, ndjson) = export_string(
+    &db,
+    Table::PieceStats,
+    Format::Ndjson,
+    &db::GameFilter::default(),
+  )
+  .await;
+  assert_eq!(count, 32);
+  let pawn: serde_json::Value = ndjson
+    .lines()
+    .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+    .find(|r| r["player_id"] == "bob" && r["piece"] == "pawn e")
+    .unwrap();
+  assert_eq!(pawn["source"], "lichess");
+  assert_eq!(pawn["times_captured"], 1);
+  assert_eq!(pawn["wins"], 0);
+}
+
+// White plays without the b1 knight.
+const KNIGHT_ODDS: &str =
+  "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq - 0 1";
+
+// Exports a knight odds game with clocks and evals as PGN, then reads it back
+// in as if it had been downloaded.
+#[tokio::test]
+async fn pgn_round_trip() {
+  let db = common::sqlite_db().await;
+  let mut board = Board::from_fen(KNIGHT_ODDS).unwrap();
+  let mut moves = Vec::new();
+  for (i, san) in
+    ["e4", "e5", "Nf3", "Nc6", "Bc4", "Bc5", "O-O", "Nf6", "Nxe5", "Nxe5"]
+      .iter()
+      .enumerate()
+  {
+    let mut m = board.make_san_move(san).unwrap();
+    m.clock_ms = Some(300_000 - 1_500 * i as i64);
+    m.elapsed_ms = Some(1_500);
+    m.set_eval(if i == 9 {
+      Eval::Mate(-7)
+    } else {
+      Eval::Centipawns(35 - 40 * i as i32)
+    });
+    moves.push(m);
+  }
+  let mut g = game("g1", "alice", "bob", 1_600_000_000);
+  g.source = "lichess.org".to_owned();
+  g.source_id = "AbCd1234".to_owned();
+  g.time_control = "300+3".to_owned();
+  g.eco = "C50".to_owned();
+  g.opening = "Italian Game".to_owned();
+  g.variant = db::ODDS_VARIANT.to_owned();
+  g.starting_fen = Some(KNIGHT_ODDS.to_owned());
+  g.result = db::BLACK_WON.to_owned();
+  g.termination = "resignation".to_owned();
+  insert(
+    &db,
+    db::GameRecord {
+      game: g.clone(),
+      moves: moves.clone(),
+      lifecycles: board.lifecycles(),
+    },
+  )
+  .await;
+
+  let (count, pgn) =
+    export_string(&db, Table::Games, Format::Pgn, &Default::default()).await;
+  assert_eq!(count, 1);
+  for header in [
+    "[Site \"https://lichess.org/AbCd1234\"]",
+    "[Result \"0-1\"]",
+    "[Variant \"From Position\"]",
+    "[SetUp \"1\"]",
+    "[FEN \"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq - 0 1\"]",
+    "[Termination \"resignation\"]",
+  ]
+  .iter()
+  {
+    assert!(pgn.contains(header), "missing {} in\n{}", header, pgn);
+  }
+
+  let mut reader = pgn_reader::BufferedReader::new_cursor(pgn.as_bytes());
+  let record = reader
+    .read_game(&mut GameScore::new())
+    .unwrap()
+    .expect("a game")
+    .unwrap_or_else(|rejection| panic!("rejected: {}", rejection));
+
+  let read = record.game;
+  assert_eq!(read.source, g.source);
+  assert_eq!(read.source_id, g.source_id);
+  assert_eq!(read.end_time, g.end_time);
+  assert_eq!(read.time_control, g.time_control);
+  assert_eq!(read.white_player_id, g.white_player_id);
+  assert_eq!(read.white_player_rating, g.white_player_rating);
+  assert_eq!(read.black_player_id, g.black_player_id);
+  assert_eq!(read.black_player_rating, g.black_player_rating);
+  assert_eq!(read.eco, g.eco);
+  assert_eq!(read.opening, g.opening);
+  assert_eq!(read.variant, g.variant);
+  assert_eq!(read.starting_fen, g.starting_fen);
+  assert_eq!(read.result, g.result);
+  assert_eq!(read.termination, g.termination);
+
+  let fields = |moves: &[db::Move]| -> Vec<_> {
+    moves
+      .iter()
+      .map(|m| {
+        (
+          m.move_num,
+          m.uci.clone(),
+          m.fen.clone(),
+          m.capture_score,
+          m.clock_ms,
+          m.elapsed_ms,
+          m.eval(),
+        )
+      })
+      .collect()
+  };
+  assert_eq!(fields(&record.moves), fields(&moves));
+
+  // The missing knight never spawned, either time.
+  let pieces = |lifecycles: &[db::PieceLifecycle]| -> Vec<_> {
+    lifecycles
+      .iter()
+      .map(|l| (l.color.clone(), l.piece.clone(), l.squares_visited.clone()))
+      .collect()
+  };
+  assert_eq!(pieces(&record.lifecycles), pieces(&board.lifecycles()));
+  assert!(!record
+    .lifecycles
+    .iter()
+    .any(|l| l.color == "white" && l.piece == "knight b"));
+}
@@ -0,0 +1,36 @@
+#!/bin/sh
+# A stand-in UCI engine for tests. It scores every position as 10 centipawns
+# per move played for the side to move, plus 500 for positions set up from a
+# FEN, and always plays e2e4. Before the real score it sends a bound, which
+# callers should ignore.
+plies=0
+bonus=0
+while read -r command rest; do
+  case "$command" in
+    uci)
+      echo "id name fake"
+      echo "uciok"
+      ;;
+    isready)
+      echo "readyok"
+      ;;
+    position)
+      case "$rest" in
+        *moves*) plies=$(echo "${rest#*moves}" | wc -w) ;;
+        *) plies=0 ;;
+      esac
+      case "$rest" in
+        fen*) bonus=500 ;;
+        *) bonus=0 ;;
+      esac
+      ;;
+    go)
+      echo "info depth 1 score cp 9999 lowerbound"
+      echo "info depth 2 seldepth 3 score cp $((plies * 10 + bonus)) nodes 42 pv e2e4"
+      echo "bestmove e2e4"
+      ;;
+    quit)
+      exit 0
+      ;;
+  esac
+done
@@ -0,0 +1,63 @@
+mod common;
+
+use fantasy_chess::db;
+use fantasy_chess::dumbchess::Board;
+use fantasy_chess::heatmap::{self, Filter, Kind};
+
+use common::{game, insert};
+
+// Squares with a nonzero count, by name.
+fn squares(heatmap: &heatmap::Heatmap, kind: Kind) -> Vec<(String, u32)> {
+  let files = ["a", "b", "c", "d", "e", "f", "g", "h"];
+  heatmap
+    .get(kind)
+    .iter()
+    .enumerate()
+    .filter(|(_, count)| **count > 0)
+    .map(|(i, count)| (format!("{}{}", files[i % 8], i / 8 + 1), *count))
+    .collect()
+}
+
+#[tokio::test]
+async fn bishop_squares() {
+  let db = common::sqlite_db().await;
+  let mut board = Board::starting();
+  let moves = "e4 e5 Nf3 Nc6 Bb5 a6 Bxc6 dxc6 O-O Bd6"
+    .split(' ')
+    .map(|san| board.make_san_move(san).unwrap())
+    .collect();
+  let record = db::GameRecord {
+    game: game("g1", "alice", "bob", 1000),
+    moves,
+    lifecycles: board.lifecycles(),
+  };
+  insert(&db, record).await;
+
+  let filter = |piece: &str| Filter {
+    player_id: Some("alice".to_owned()),
+    piece: Some(piece.to_owned()),
+    ..Default::default()
+  };
+  let bishop = heatmap::heatmap(&db, &filter("bishop f")).await.unwrap();
+  assert_eq!(
+    squares(&bishop, Kind::Occupancy),
+    vec![("f1".to_owned(), 1), ("b5".to_owned(), 1), ("c6".to_owned(), 1)]
+  );
+  assert_eq!(squares(&bishop, Kind::Captures), vec![("c6".to_owned(), 1)]);
+
+  // A bishop that never moves still stood on its starting square, and only
+  // alice's bishop counts.
+  let bishop = heatmap::heatmap(&db, &filter("bishop c")).await.unwrap();
+  assert_eq!(squares(&bishop, Kind::Occupancy), vec![("c1".to_owned(), 1)]);
+  assert!(squares(&bishop, Kind::Captures).is_empty());
+
+  let black = Filter { color: Some("black".to_owned()), ..filter("bishop f") };
+  let bishop = heatmap::heatmap(&db, &black).await.unwrap();
+  assert!(squares(&bishop, Kind::Occupancy).is_empty());
+  let black = Filter { player_id: None, ..black };
+  let bishop = heatmap::heatmap(&db, &black).await.unwrap();
+  assert_eq!(
+    squares(&bishop, Kind::Occupancy),
+    vec![("d6".to_owned(), 1), ("f8".to_owned(), 1)]
+  );
+}
@@ -0,0 +1,382 @@
+mod common;
+
+use fantasy_chess::db::{self, Dialect};
+use fantasy_chess::league::{
+  self, League, PieceId, TradeStatus, WaiverClaim, WaiverMode,
+};
+use fantasy_chess::stats::Scoring;
+
+use common::{capture, game, insert};
+
+// Creates a league whose only team holds alice's g knight.
+async fn league_holding_knight(
+  db: &sqlx::Pool<sqlx::Any>,
+  scoring: Scoring,
+) -> League {
+  let dialect = Dialect::of(db);
+  let mut league = League::new("Test", WaiverMode::Priority, 0, scoring);
+  let team_id = league.add_team("A", 100).id.clone();
+  let knight = PieceId {
+    source: "lichess".to_owned(),
+    player_id: "alice".to_owned(),
+    piece: "knight g".to_owned(),
+  };
+  let mut queries = vec![league.insert_query(dialect)];
+  queries.extend(league.teams.iter().map(|t| t.upsert_query(dialect)));
+  for t in league.add_free_agent(&team_id, knight, None, 0).unwrap() {
+    queries.push(t.insert_query(dialect));
+  }
+  for query in queries {
+    query.execute(db).await.unwrap();
+  }
+  League::load(db, &league.id).await.unwrap()
+}
+
+#[tokio::test]
+async fn scoring() {
+  let db = common::sqlite_db().await;
+  // alice's knight takes bob's queen; bob went into the game rated 1600.
+  insert(
+    &db,
+    db::GameRecord {
+      game: game("g1", "alice", "bob", 1000),
+      moves: vec![capture(3, "white", "knight g", ("f3", "d4"), "queen", 9)],
+      lifecycles: Vec::new(),
+    },
+  )
+  .await;
+
+  let flat = league_holding_knight(&db, Scoring::Flat).await;
+  assert_eq!(flat.scoring, Scoring::Flat);
+  let team_id = &flat.teams[0].id;
+  assert_eq!(league::team_score(&db, &flat, team_id).await.unwrap(), 9);
+
+  let weighted = league_holding_knight(&db, Scoring::RatingWeighted).await;
+  assert_eq!(weighted.scoring, Scoring::RatingWeighted);
+  let team_id = &weighted.teams[0].id;
+  // 9 * 1600 / 1500, rounded
+  assert_eq!(league::team_score(&db, &weighted, team_id).await.unwrap(), 10);
+}
+
+#[tokio::test]
+async fn rating_at() {
+  let db = common::sqlite_db().await;
+  for (id, end_time, rating) in [("g1", 1000, 1500), ("g2", 2000, 1550)].iter()
+  {
+    let mut g = game(id, "alice", "bob", *end_time);
+    g.white_player_rating = *rating;
+    for query in g.rating_queries(Dialect::of(&db)) {
+      query.execute(&db).await.unwrap();
+    }
+  }
+
+  let rating_at =
+    |at| db::rating_at(&db, "lichess", "alice", db::TimeClass::Blitz, at);
+  // The rating going into the next game is the one held until then.
+  assert_eq!(rating_at(500).await.unwrap(), Some(1500));
+  assert_eq!(rating_at(1000).await.unwrap(), Some(1550));
+  assert_eq!(rating_at(1500).await.unwrap(), Some(1550));
+  // With no later game, the last one's rating is the best there is.
+  assert_eq!(rating_at(2500).await.unwrap(), Some(1550));
+  let unrated =
+    db::rating_at(&db, "lichess", "carol", db::TimeClass::Blitz, 1500).await;
+  assert_eq!(unrated.unwrap(), None);
+}
+
+fn piece(player_id: &str, piece: &str) -> PieceId {
+  PieceId {
+    source: "lichess".to_owned(),
+    player_id: player_id.to_owned(),
+    piece: piece.to_owned(),
+  }
+}
+
+// A league of teams A, B and C, in that waiver priority order, each with a FAAB
+// budget of 100.
+fn three_teams(waiver_mode: WaiverMode) -> (League, Vec<String>) {
+  let mut league = League::new("Test", waiver_mode, 0, Scoring::Flat);
+  let ids = ["A", "B", "C"]
+    .iter()
+    .map(|name| league.add_team(name, 100).id.clone())
+    .collect();
+  (league, ids)
+}
+
+// Builds a claim directly, skipping the checks claim_waiver makes, to test
+// how waiver runs handle claims the league has since outgrown.
+fn claim(
+  league: &League,
+  team_id: &str,
+  add: PieceId,
+  drop: Option<PieceId>,
+  bid: i32,
+  submitted_time: i64,
+) -> WaiverClaim {
+  WaiverClaim {
+    id: uuid::Uuid::new_v4().to_string(),
+    league_id: league.id.clone(),
+    team_id: team_id.to_owned(),
+    add,
+    drop,
+    bid,
+    submitted_time,
+  }
+}
+
+fn priorities(league: &League) -> Vec<i32> {
+  league.teams.iter().map(|t| t.waiver_priority).collect()
+}
+
+fn budgets(league: &League) -> Vec<i32> {
+  league.teams.iter().map(|t| t.faab_budget).collect()
+}
+
+#[test]
+fn waiver_priority_rotates() {
+  let (mut league, teams) = three_teams(WaiverMode::Priority);
+  let knight = piece("alice", "knight g");
+  let bishop = piece("alice", "bishop f");
+  // C claimed first, but A has the better priority.
+  let claims = vec![
+    claim(&league, &teams[2], knight.clone(), None, 0, 1),
+    claim(&league, &teams[0], knight.clone(), None, 0, 2),
+  ];
+  league.process_waivers(claims, 10).unwrap();
+  assert_eq!(league.holder(&knight, 10), Some(teams[0].as_str()));
+  // A drops to the back; everyone behind it moves up.
+  assert_eq!(priorities(&league), [3, 1, 2]);
+
+  let claims = vec![
+    claim(&league, &teams[0], bishop.clone(), None, 0, 11),
+    claim(&league, &teams[1], bishop.clone(), None, 0, 12),
+  ];
+  league.process_waivers(claims, 20).unwrap();
+  assert_eq!(league.holder(&bishop, 20), Some(teams[1].as_str()));
+  assert_eq!(priorities(&league), [2, 3, 1]);
+}
+
+#[test]
+fn faab_bids() {
+  let (mut league, teams) = three_teams(WaiverMode::Faab);
+  let knight = piece("alice", "knight g");
+  let bishop = piece("alice", "bishop f");
+  let rook = piece("alice", "rook a");
+  let claims = vec![
+    // The highest bid wins, whoever has priority.
+    claim(&league, &teams[0], knight.clone(), None, 10, 1),
+    claim(&league, &teams[1], knight.clone(), None, 30, 2),
+    // Equal bids go to the better waiver priority.
+    claim(&league, &teams[2], bishop.clone(), None, 20, 3),
+    claim(&league, &teams[0], bishop.clone(), None, 20, 4),
+    // B has only 70 left after the knight, so its bid is skipped.
+    claim(&league, &teams[1], rook.clone(), None, 80, 5),
+    claim(&league, &teams[2], rook.clone(), None, 5, 6),
+  ];
+  let recorded = league.process_waivers(claims, 10).unwrap();
+  assert_eq!(recorded.len(), 3);
+  assert_eq!(league.holder(&knight, 10), Some(teams[1].as_str()));
+  assert_eq!(league.holder(&bishop, 10), Some(teams[0].as_str()));
+  assert_eq!(league.holder(&rook, 10), Some(teams[2].as_str()));
+  assert_eq!(budgets(&league), [80, 70, 95]);
+  // FAAB leagues don't rotate priority.
+  assert_eq!(priorities(&league), [1, 2, 3]);
+}
+
+#[test]
+fn waiver_drop_gone() {
+  let (mut league, teams) = three_teams(WaiverMode::Priority);
+  let knight = piece("alice", "knight g");
+  let bishop = piece("alice", "bishop f");
+  league.add_free_agent(&teams[0], bishop.clone(), None, 0).unwrap();
+  let claims = vec![
+    claim(&league, &teams[0], knight.clone(), Some(bishop.clone()), 0, 1),
+    claim(&league, &teams[1], knight.clone(), None, 0, 2),
+  ];
+  // A no longer holds the piece it offered to drop by the time waivers run.
+  league.drop_piece(&teams[0], bishop.clone(), 5).unwrap();
+  let recorded = league.process_waivers(claims, 10).unwrap();
+  assert_eq!(recorded.len(), 1);
+  assert_eq!(league.holder(&knight, 10), Some(teams[1].as_str()));
+  assert_eq!(league.roster(&teams[0], 10), []);
+  assert_eq!(priorities(&league), [1, 3, 2]);
+}
+
+#[test]
+fn backdated_add() {
+  let (mut league, teams) = three_teams(WaiverMode::Priority);
+  let knight = piece("alice", "knight g");
+  league.add_free_agent(&teams[1], knight.clone(), None, 100).unwrap();
+  // A's add would open a window still open when B picks the piece up.
+  assert!(matches!(
+    league.add_free_agent(&teams[0], knight.clone(), None, 50),
+    Err(league::Error::PieceMovesLater { time: 50, .. })
+  ));
+  let claims = vec![claim(&league, &teams[0], knight.clone(), None, 0, 1)];
+  assert!(league.process_waivers(claims, 50).unwrap().is_empty());
+  assert_eq!(league.holding_windows(&teams[0]).len(), 0);
+  assert_eq!(league.holder(&knight, 50), None);
+}
+
+#[test]
+fn trade_veto() {
+  let (mut league, teams) = three_teams(WaiverMode::Priority);
+  let knight = piece("alice", "knight g");
+  let bishop = piece("bob", "bishop f");
+  league.add_free_agent(&teams[0], knight.clone(), None, 0).unwrap();
+  league.add_free_agent(&teams[1], bishop.clone(), None, 0).unwrap();
+  let mut trade = league
+    .propose_trade(&teams[0], &teams[1], vec![knight], vec![bishop], 1)
+    .unwrap();
+  assert_eq!(trade.status, TradeStatus::Proposed);
+  // Only accepted trades can be vetoed.
+  assert!(matches!(
+    trade.veto(),
+    Err(league::Error::InvalidTradeState {
+      status: TradeStatus::Proposed,
+      expected: TradeStatus::Accepted,
+      ..
+    })
+  ));
+  trade.accept().unwrap();
+  trade.veto().unwrap();
+  assert_eq!(trade.status, TradeStatus::Vetoed);
+  assert!(trade.accept().is_err());
+  assert!(trade.reject().is_err());
+  assert!(league.execute_trade(&mut trade, 2).is_err());
+  assert_eq!(league.transactions.len(), 2);
+}
+
+#[test]
+fn trade_piece_moved() {
+  let (mut league, teams) = three_teams(WaiverMode::Priority);
+  let knight = piece("alice", "knight g");
+  let bishop = piece("bob", "bishop f");
+  league.add_free_agent(&teams[0], knight.clone(), None, 0).unwrap();
+  league.add_free_agent(&teams[1], bishop.clone(), None, 0).unwrap();
+  let mut trade = league
+    .propose_trade(
+      &teams[0],
+      &teams[1],
+      vec![knight.clone()],
+      vec![bishop.clone()],
+      1,
+    )
+    .unwrap();
+  trade.accept().unwrap();
+  // A drops the knight while the trade is in review.
+  league.drop_piece(&teams[0], knight.clone(), 5).unwrap();
+  assert!(matches!(
+    league.execute_trade(&mut trade, 10),
+    Err(league::Error::PieceNotHeld { piece, .. }) if piece == knight
+  ));
+  assert_eq!(trade.status, TradeStatus::Accepted);
+  assert_eq!(league.transactions.len(), 3);
+  assert_eq!(league.holder(&bishop, 10), Some(teams[1].as_str()));
+
+  // A trade that still holds together swaps both sides at once.
+  league.add_free_agent(&teams[0], knight.clone(), None, 20).unwrap();
+  assert_eq!(league.execute_trade(&mut trade, 30).unwrap().len(), 4);
+  assert_eq!(trade.status, TradeStatus::Executed);
+  assert_eq!(league.trades[0].status, TradeStatus::Executed);
+  assert_eq!(league.roster(&teams[0], 30), [bishop]);
+  assert_eq!(league.roster(&teams[1], 30), std::slice::from_ref(&knight));
+  assert_eq!(league.roster(&teams[0], 25), [knight]);
+}
+
+#[tokio::test]
+async fn holding_window_scoring() {
+  let db = common::sqlite_db().await;
+  // Each game's capture scores differently, so the total shows which counted.
+  for (id, end_time, score) in
+    [("g1", 500, 1), ("g2", 1000, 3), ("g3", 1999, 5), ("g4", 2000, 9)].iter()
+  {
+    insert(
+      &db,
+      db::GameRecord {
+        game: game(id, "alice", "bob", *end_time),
+        moves: vec![capture(
+          3,
+          "white",
+          "knight g",
+          ("f3", "d4"),
+          "pawn d",
+          *score,
+        )],
+        lifecycles: Vec::new(),
+      },
+    )
+    .await;
+  }
+
+  let (mut league, teams) = three_teams(WaiverMode::Priority);
+  let knight = piece("alice", "knight g");
+  league.add_free_agent(&teams[0], knight.clone(), None, 1000).unwrap();
+  league.drop_piece(&teams[0], knight.clone(), 2000).unwrap();
+  let windows = league.holding_windows(&teams[0]);
+  assert_eq!(windows.len(), 1);
+  assert_eq!((windows[0].start, windows[0].end), (1000, Some(2000)));
+  assert!(windows[0].contains(1000));
+  assert!(!windows[0].contains(2000));
+
+  // Games ending at the add count; games ending at the drop don't.
+  assert_eq!(league::team_score(&db, &league, &teams[0]).await.unwrap(), 8);
+  assert_eq!(
+    league::period_score(&db, &league, &teams[0], 1500, None).await.unwrap(),
+    5
+  );
+  assert_eq!(league::team_score(&db, &league, &teams[1]).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn waiver_claims() {
+  let db = common::sqlite_db().await;
+  let dialect = Dialect::of(&db);
+  let (mut league, teams) = three_teams(WaiverMode::Faab);
+  let knight = piece("alice", "knight g");
+  let bishop = piece("alice", "bishop f");
+  league.add_free_agent(&teams[0], bishop.clone(), None, 0).unwrap();
+  league.add_free_agent(&teams[1], knight.clone(), None, 0).unwrap();
+
+  let claim = |team, drop, bid| {
+    league.claim_waiver(team, piece("bob", "rook a"), drop, bid, 10)
+  };
+  assert!(matches!(
+    claim(&teams[0], None, 101),
+    Err(league::Error::BidOverBudget { bid: 101, budget: 100, .. })
+  ));
+  assert!(matches!(
+    claim(&teams[0], Some(knight.clone()), 0),
+    Err(league::Error::PieceNotHeld { .. })
+  ));
+  assert!(matches!(
+    league.claim_waiver(&teams[0], knight, None, 0, 10),
+    Err(league::Error::PieceAlreadyRostered(_))
+  ));
+
+  let with_drop = claim(&teams[0], Some(bishop.clone()), 40).unwrap();
+  let without = claim(&teams[2], None, 0).unwrap();
+  with_drop.insert_query(dialect).execute(&db).await.unwrap();
+  without.insert_query(dialect).execute(&db).await.unwrap();
+  let pending = league::waiver_claims(&db, &league.id).await.unwrap();
+  assert_eq!(pending.len(), 2);
+  assert_eq!(pending[0].id, with_drop.id);
+  assert_eq!(pending[0].drop, Some(bishop));
+  assert_eq!(pending[0].bid, 40);
+  assert_eq!(pending[1].drop, None);
+  assert_eq!(pending[1].add, piece("bob", "rook a"));
+
+  with_drop.delete_query(dialect).execute(&db).await.unwrap();
+  let pending = league::waiver_claims(&db, &league.id).await.unwrap();
+  assert_eq!(pending.len(), 1);
+}
+
+#[test]
+fn piece_ids() {
+  let knight: PieceId = "lichess/alice knight g".parse().unwrap();
+  assert_eq!(knight, piece("alice", "knight g"));
+  assert_eq!(knight.to_string(), "lichess/alice knight g");
+  let queen: PieceId = "person/p1 queen".parse().unwrap();
+  assert_eq!(queen, PieceId::person("p1", "queen"));
+  assert!("lichess alice knight".parse::<PieceId>().is_err());
+  assert!("lichess/alice".parse::<PieceId>().is_err());
+}
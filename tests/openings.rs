@@ -0,0 +1,48 @@
+use fantasy_chess::db;
+use fantasy_chess::dumbchess::Board;
+use fantasy_chess::openings::{self, Opening};
+
+fn classify(moves: &str) -> Option<Opening> {
+  openings::classify(&moves.split(' ').collect::<Vec<_>>())
+}
+
+#[test]
+fn classifies_most_specific_line() {
+  assert_eq!(
+    classify("e2e4 e7e6 d2d4 d7d5 e4d5 e6d5 g1f3"),
+    Some(Opening { eco: "C01", name: "French Defense: Exchange Variation" })
+  );
+  assert_eq!(
+    classify("e2e4 c7c5 g1f3 d7d6 d2d4 c5d4 f3d4 g8f6 b1c3 a7a6 c1e3"),
+    Some(Opening { eco: "B90", name: "Sicilian Defense: Najdorf Variation" })
+  );
+  assert_eq!(classify("e2e4 e7e6").map(|o| o.eco), Some("C00"));
+  assert_eq!(classify("h2h3 a7a6 h3h4"), None);
+}
+
+fn classify_game(eco: &str, opening: &str, sans: &str) -> db::Game {
+  let mut board = Board::starting();
+  let moves: Vec<db::Move> =
+    sans.split(' ').map(|san| board.make_san_move(san).unwrap()).collect();
+  let mut game = db::Game {
+    eco: eco.to_owned(),
+    opening: opening.to_owned(),
+    ..db::Game::empty()
+  };
+  openings::classify_game(&mut game, &moves);
+  game
+}
+
+#[test]
+fn keeps_source_opening() {
+  let game =
+    classify_game("C00", "French Defense: Normal Variation", "e4 e6 d4 d5");
+  assert_eq!(game.eco, "C00");
+  assert_eq!(game.opening, "French Defense: Normal Variation");
+
+  let game = classify_game("", "", "e4 e6 d4 d5 exd5 exd5 Nf3");
+  assert_eq!(game.eco, "C01");
+  assert_eq!(game.opening, "French Defense: Exchange Variation");
+  // A bare ECO code is replaced only when the book knows the line.
+  assert_eq!(classify_game("A00", "", "h3 a6 h4").eco, "A00");
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use fantasy_chess::db;
+use fantasy_chess::pgn::{GameChunks, GameScore, HeaderFilter};
+
+fn read(
+  pgn: &str,
+  filter: &HeaderFilter,
+) -> Result<db::GameRecord, db::Rejection> {
+  pgn_reader::BufferedReader::new_cursor(pgn.as_bytes())
+    .read_game(&mut GameScore::with_filter(Arc::new(filter.clone())))
+    .unwrap()
+    .expect("a game")
+}
+
+fn game(event: &str) -> String {
+  format!(
+    "[Event \"{}\"]\n\
+     [Site \"Chess.com\"]\n\
+     [Date \"2021.03.01\"]\n\
+     [White \"alice\"]\n\
+     [Black \"bob\"]\n\
+     [Result \"1-0\"]\n\
+     [EndTime \"12:00:00 PST\"]\n\n\
+     1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0\n",
+    event
+  )
+}
+
+#[test]
+fn rated_only() {
+  let filter = HeaderFilter { rated_only: true, ..Default::default() };
+  // chess.com doesn't say whether a game was rated.
+  assert!(read(&game("Live Chess"), &filter).is_ok());
+  assert!(read(&game("Rated Blitz game"), &filter).is_ok());
+  match read(&game("Casual Blitz game"), &filter) {
+    Err(db::Rejection::Skipped(reason)) => assert_eq!(reason, "unrated"),
+    other => panic!("expected a skip, got {:?}", other.map(|r| r.game)),
+  }
+}
+
+#[test]
+fn skipped_before_nonstandard() {
+  let chess960 =
+    game("Casual Blitz game").replace("[Site", "[Variant \"Chess960\"]\n[Site");
+  let filter = HeaderFilter { rated_only: true, ..Default::default() };
+  match read(&chess960, &filter) {
+    Err(db::Rejection::Skipped(reason)) => assert_eq!(reason, "unrated"),
+    other => panic!("expected a skip, got {:?}", other.map(|r| r.game)),
+  }
+  match read(&chess960, &HeaderFilter::default()) {
+    Err(db::Rejection::Nonstandard(variant)) => assert_eq!(variant, "chess960"),
+    other => panic!("expected nonstandard, got {:?}", other.map(|r| r.game)),
+  }
+}
+
+#[test]
+fn chunks_split_at_headers() {
+  let commented = game("Live Chess").replace(
+    "1. e4 e5",
+    "1. e4 { a stray { brace } e5 {\n[%clk 0:03:00]\n} ; { not a comment",
+  );
+  let pgn = format!("{}\n{}\n{}", commented, game("Live Chess"), game("Other"));
+  let chunks: Vec<Vec<u8>> =
+    GameChunks::new(pgn.as_bytes(), 1).collect::<std::io::Result<_>>().unwrap();
+  assert_eq!(chunks.len(), 3);
+  assert!(String::from_utf8_lossy(&chunks[0]).contains("[%clk 0:03:00]"));
+  assert!(String::from_utf8_lossy(&chunks[2]).contains("Other"));
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use std::collections::HashMap;
+
+use fantasy_chess::db;
+use fantasy_chess::stats::{self, Filter, PieceStats};
+
+use common::{capture, game, insert};
+
+// Two of alice's games: a win as white against a 1600 in a 5 minute game, and
+// a later draw as black against a 1500 in a 1 minute game. The moves only need
+// to be plausible, since piece stats read what was stored.
+async fn games() -> sqlx::Pool<sqlx::Any> {
+  let db = common::sqlite_db().await;
+
+  let mut g1 = game("g1", "alice", "bob", 1000);
+  g1.result = db::WHITE_WON.to_owned();
+  let mut promotion = capture(6, "white", "pawn a", ("a7", "a8"), "", 0);
+  promotion.promotion_value = 8;
+  let mut mate = capture(8, "white", "knight g", ("e5", "f7"), "", 0);
+  mate.checkmate = true;
+  insert(
+    &db,
+    db::GameRecord {
+      game: g1,
+      moves: vec![
+        capture(0, "white", "knight g", ("g1", "f3"), "", 0),
+        capture(1, "black", "pawn e", ("e7", "e5"), "", 0),
+        capture(2, "white", "knight g", ("f3", "e5"), "pawn e", 1),
+        capture(3, "black", "queen d", ("d8", "g5"), "", 0),
+        capture(4, "white", "pawn d", ("d2", "d4"), "", 0),
+        capture(5, "black", "queen d", ("g5", "d2"), "pawn d", 1),
+        promotion,
+        capture(7, "black", "king e", ("e8", "d8"), "", 0),
+        mate,
+      ],
+      lifecycles: Vec::new(),
+    },
+  )
+  .await;
+
+  let mut g2 = game("g2", "bob", "alice", 2000);
+  g2.time_control = "60+0".to_owned();
+  g2.result = db::DRAWN.to_owned();
+  insert(
+    &db,
+    db::GameRecord {
+      game: g2,
+      moves: vec![
+        capture(0, "white", "pawn e", ("e2", "e4"), "", 0),
+        capture(1, "black", "pawn d", ("d7", "d5"), "", 0),
+        capture(2, "white", "pawn e", ("e4", "d5"), "pawn d", 1),
+        capture(3, "black", "queen d", ("d8", "d5"), "pawn e", 1),
+      ],
+      lifecycles: Vec::new(),
+    },
+  )
+  .await;
+  db
+}
+
+async fn piece_stats(
+  db: &sqlx::Pool<sqlx::Any>,
+  filter: &Filter,
+) -> HashMap<String, PieceStats> {
+  stats::piece_stats(db, "alice", filter)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|s| (s.piece.clone(), s))
+    .collect()
+}
+
+#[tokio::test]
+async fn known_game() {
+  let db = games().await;
+  let filter = Filter { until: Some(2000), ..Default::default() };
+  let stats = piece_stats(&db, &filter).await;
+
+  let knight = &stats["knight g"];
+  assert_eq!(
+    (knight.games, knight.captures, knight.points, knight.times_captured),
+    (1, 1, 1, 0)
+  );
+  assert_eq!((knight.checkmates, knight.wins), (1, 1));
+  // A piece that's never captured survives the whole game.
+  assert_eq!(knight.survival_plies, 9);
+  assert_eq!(knight.average_survival_ply(), 9.0);
+
+  let pawn = &stats["pawn d"];
+  assert_eq!((pawn.times_captured, pawn.survival_plies), (1, 6));
+  assert_eq!((pawn.captures, pawn.checkmates), (0, 0));
+
+  let promoted = &stats["pawn a"];
+  assert_eq!(promoted.promotions, 1);
+  assert_eq!(promoted.promotion_rate(), 1.0);
+  assert_eq!(stats["pawn b"].promotion_rate(), 0.0);
+}
+
+#[tokio::test]
+async fn filters() {
+  let db = games().await;
+  // Games played by alice's king, and alice's captures with her queen, which
+  // only happen in the second game.
+  let totals = |stats: HashMap<String, PieceStats>| {
+    (stats["king e"].games, stats["queen d"].captures)
+  };
+  let both = (2, 1);
+  let (first, second) = ((1, 0), (1, 1));
+  for (filter, expected) in vec![
+    (Filter::default(), both),
+    (Filter { since: Some(2000), ..Default::default() }, second),
+    (Filter { until: Some(2000), ..Default::default() }, first),
+    (
+      Filter { since: Some(1000), until: Some(2001), ..Default::default() },
+      both,
+    ),
+    (Filter { color: Some("white".to_owned()), ..Default::default() }, first),
+    (Filter { color: Some("black".to_owned()), ..Default::default() }, second),
+    (
+      Filter { time_control: Some("60+0".to_owned()), ..Default::default() },
+      second,
+    ),
+    (Filter { min_opponent_rating: Some(1550), ..Default::default() }, first),
+    (Filter { max_opponent_rating: Some(1550), ..Default::default() }, second),
+    (
+      Filter {
+        min_opponent_rating: Some(1500),
+        max_opponent_rating: Some(1600),
+        ..Default::default()
+      },
+      both,
+    ),
+  ] {
+    let stats = piece_stats(&db, &filter).await;
+    assert_eq!(totals(stats), expected, "{:?}", filter);
+  }
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use fantasy_chess::db;
+use fantasy_chess::eval::Eval;
+use fantasy_chess::uci::{self, Engine, Limit};
+
+use common::{capture, game, insert};
+
+const FAKE_ENGINE: &str =
+  concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fake_uci.sh");
+
+const KNIGHT_ODDS: &str =
+  "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq - 0 1";
+
+fn moves(uci: &[&str]) -> Vec<String> {
+  uci.iter().map(|m| m.to_string()).collect()
+}
+
+#[tokio::test]
+async fn evaluate() {
+  let mut engine = Engine::start(FAKE_ENGINE).await.unwrap();
+  engine.new_game().await.unwrap();
+
+  // The bound sent first is skipped in favor of the final score.
+  let eval = engine.evaluate(None, &[], Limit::Depth(2)).await.unwrap();
+  assert_eq!(eval, Some(Eval::Centipawns(0)));
+  let eval = engine
+    .evaluate(None, &moves(&["e2e4", "e7e5", "g1f3"]), Limit::Nodes(1000))
+    .await
+    .unwrap();
+  assert_eq!(eval, Some(Eval::Centipawns(30)));
+
+  // Positions set up from a FEN score 500 more.
+  let eval =
+    engine.evaluate(Some(KNIGHT_ODDS), &[], Limit::Depth(2)).await.unwrap();
+  assert_eq!(eval, Some(Eval::Centipawns(500)));
+  let eval = engine
+    .evaluate(Some(KNIGHT_ODDS), &moves(&["e2e4"]), Limit::Depth(2))
+    .await
+    .unwrap();
+  assert_eq!(eval, Some(Eval::Centipawns(510)));
+  engine.quit().await.unwrap();
+}
+
+#[tokio::test]
+async fn engine_exits() {
+  match Engine::start("true").await {
+    Err(uci::Error::EngineExited) => (),
+    Err(e) => panic!("unexpected error: {}", e),
+    Ok(_) => panic!("expected the engine to exit"),
+  }
+}
+
+#[tokio::test]
+async fn analyze_game() {
+  let db = common::sqlite_db().await;
+  insert(
+    &db,
+    db::GameRecord {
+      game: game("g1", "alice", "bob", 1000),
+      moves: vec![
+        capture(1, "white", "pawn e", ("e2", "e4"), "", 0),
+        capture(2, "black", "pawn e", ("e7", "e5"), "", 0),
+        capture(3, "white", "knight g", ("g1", "f3"), "", 0),
+      ],
+      lifecycles: Vec::new(),
+    },
+  )
+  .await;
+
+  let mut engine = Engine::start(FAKE_ENGINE).await.unwrap();
+  let analyzed =
+    uci::analyze_game(&db, &mut engine, "g1", Limit::Depth(2)).await.unwrap();
+  engine.quit().await.unwrap();
+
+  // The engine scores for the side to move; stored evals are from white's
+  // point of view.
+  let stored = db::moves_for_game(&db, "g1").await.unwrap();
+  let evals: Vec<Option<i32>> = stored.iter().map(|m| m.eval_cp).collect();
+  assert_eq!(evals, [Some(-10), Some(20), Some(-30)]);
+  assert!(stored.iter().all(|m| m.eval_mate.is_none()));
+  assert!(stored.iter().all(|m| m.cp_loss.is_some()));
+
+  let read = db::game(&db, "g1").await.unwrap().unwrap();
+  assert!(read.white_accuracy.is_some());
+  assert!(read.black_accuracy.is_some());
+  assert_eq!(read.white_accuracy, analyzed.white_accuracy);
+  assert_eq!(read.black_accuracy, analyzed.black_accuracy);
+
+  // Odds games are analyzed from their starting position.
+  let mut odds = game("g2", "alice", "bob", 2000);
+  odds.variant = db::ODDS_VARIANT.to_owned();
+  odds.starting_fen = Some(KNIGHT_ODDS.to_owned());
+  insert(
+    &db,
+    db::GameRecord {
+      game: odds,
+      moves: vec![capture(1, "white", "pawn e", ("e2", "e4"), "", 0)],
+      lifecycles: Vec::new(),
+    },
+  )
+  .await;
+  let mut engine = Engine::start(FAKE_ENGINE).await.unwrap();
+  uci::analyze_game(&db, &mut engine, "g2", Limit::Depth(2)).await.unwrap();
+  let stored = db::moves_for_game(&db, "g2").await.unwrap();
+  assert_eq!(stored[0].eval_cp, Some(-510));
+
+  match uci::analyze_game(&db, &mut engine, "missing", Limit::Depth(2)).await {
+    Err(uci::Error::GameNotFound(id)) => assert_eq!(id, "missing"),
+    _ => panic!("expected GameNotFound"),
+  }
+}